@@ -14,7 +14,7 @@ fn gen_table() -> [Option<String>; 256] {
     const INIT: Option<String> = None;
     let mut out = [INIT; 256];
 
-    let reg8_enum = ["B", "C", "D", "E", "H", "L", "HLPtr(com)", "A"];
+    let reg8_enum = ["B", "C", "D", "E", "H", "L", "HLPtr", "A"];
     let reg16_enum = ["BC", "DE", "HL", "AF"];
 
     // NOP
@@ -34,7 +34,7 @@ fn gen_table() -> [Option<String>; 256] {
     // XOR r8
     // 0xA8 to 0xAF
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0xA8 | i, &format!("self.xor_r8(Reg8::{x})"));
+        set_opcode(&mut out, 0xA8 | i, &format!("self.xor_r8(com, Reg8::{x})"));
     }
 
     // LD (r16), A
@@ -64,8 +64,8 @@ fn gen_table() -> [Option<String>; 256] {
         0x1A,
         "let addr = Reg16::DE.read(self); self.a = self.read8(com, addr);",
     );
-    set_opcode(&mut out, 0x2A, "let addr = Reg16::HL.read(self); self.a = self.read8(com, addr); Reg16::HL.write(self, addr.wrapping_add(1));");
-    set_opcode(&mut out, 0x3A, "let addr = Reg16::HL.read(self); self.a = self.read8(com, addr); Reg16::HL.write(self, addr.wrapping_sub(1));");
+    set_opcode(&mut out, 0x2A, "let addr = Reg16::HL.read(self); self.a = self.read8_idu(com, addr); Reg16::HL.write(self, addr.wrapping_add(1));");
+    set_opcode(&mut out, 0x3A, "let addr = Reg16::HL.read(self); self.a = self.read8_idu(com, addr); Reg16::HL.write(self, addr.wrapping_sub(1));");
 
     // 0xCB prefix
     set_opcode(&mut out, 0xCB, "self.handle_cb(com);");
@@ -85,7 +85,7 @@ fn gen_table() -> [Option<String>; 256] {
         set_opcode(
             &mut out,
             hi | lo,
-            &format!("let imm = self.fetch8(com); self.ld_r8_d8(imm, Reg8::{x});"),
+            &format!("let imm = self.fetch8(com); self.ld_r8_d8(com, imm, Reg8::{x});"),
         );
     }
 
@@ -102,7 +102,7 @@ fn gen_table() -> [Option<String>; 256] {
     for (i, x) in reg8_enum.iter().enumerate() {
         let hi = (i / 2) << 4;
         let lo = if i % 2 == 0 { 0x04 } else { 0x0C };
-        set_opcode(&mut out, hi | lo, &format!("self.inc_r8(Reg8::{x});"));
+        set_opcode(&mut out, hi | lo, &format!("self.inc_r8(com, Reg8::{x});"));
     }
 
     // DEC r8
@@ -110,7 +110,7 @@ fn gen_table() -> [Option<String>; 256] {
     for (i, x) in reg8_enum.iter().enumerate() {
         let hi = (i / 2) << 4;
         let lo = if i % 2 == 0 { 0x05 } else { 0x0D };
-        set_opcode(&mut out, hi | lo, &format!("self.dec_r8(Reg8::{x});"));
+        set_opcode(&mut out, hi | lo, &format!("self.dec_r8(com, Reg8::{x});"));
     }
 
     // LD r8, r8
@@ -123,7 +123,9 @@ fn gen_table() -> [Option<String>; 256] {
                 set_opcode(
                     &mut out,
                     hi | lo,
-                    &format!("let val = Reg8::{y}.read(self); Reg8::{x}.write(self, val);"),
+                    &format!(
+                        "let val = Reg8::{y}.read(self, com); Reg8::{x}.write(self, com, val);"
+                    ),
                 );
             }
         }
@@ -271,25 +273,29 @@ fn gen_table() -> [Option<String>; 256] {
     // SUB r8
     // 0x90 to 0x97
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0x90 | i, &format!("self.sub_r8(Reg8::{x});"));
+        set_opcode(&mut out, 0x90 | i, &format!("self.sub_r8(com, Reg8::{x});"));
     }
 
     // CP r8
     // 0xB8 to 0xBF
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0xB8 | i, &format!("self.cp_r8(Reg8::{x});"));
+        set_opcode(&mut out, 0xB8 | i, &format!("self.cp_r8(com, Reg8::{x});"));
     }
 
     // ADD A, r8
     // 0x80 to 0x87
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0x80 | i, &format!("self.add_a_r8(Reg8::{x});"));
+        set_opcode(
+            &mut out,
+            0x80 | i,
+            &format!("self.add_a_r8(com, Reg8::{x});"),
+        );
     }
 
     // OR r8
     // 0xB0 to 0xB7
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0xB0 | i, &format!("self.or_r8(Reg8::{x});"));
+        set_opcode(&mut out, 0xB0 | i, &format!("self.or_r8(com, Reg8::{x});"));
     }
 
     // JP a16
@@ -532,19 +538,31 @@ fn gen_table() -> [Option<String>; 256] {
     // ADC A, r8
     // 0x88 to 0x8F
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0x88 | i, &format!("self.adc_a_r8(Reg8::{x});"));
+        set_opcode(
+            &mut out,
+            0x88 | i,
+            &format!("self.adc_a_r8(com, Reg8::{x});"),
+        );
     }
 
     // SBC A, r8
     // 0x98 to 0x9F
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0x98 | i, &format!("self.sbc_a_r8(Reg8::{x});"));
+        set_opcode(
+            &mut out,
+            0x98 | i,
+            &format!("self.sbc_a_r8(com, Reg8::{x});"),
+        );
     }
 
     // AND r8
     // 0xA0 to 0xA7
     for (i, x) in reg8_enum.iter().enumerate() {
-        set_opcode(&mut out, 0xA0 | i, &format!("self.and_a_r8(Reg8::{x});"));
+        set_opcode(
+            &mut out,
+            0xA0 | i,
+            &format!("self.and_a_r8(com, Reg8::{x});"),
+        );
     }
 
     out
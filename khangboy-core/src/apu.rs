@@ -1,4 +1,4 @@
-use crate::util::BitIndex;
+use crate::{savestate::impl_state, util::BitIndex};
 
 // The audio processing unit, which handles audio stuff
 #[derive(Default)]
@@ -109,3 +109,9 @@ impl APU {
         self.wave_ram[addr as usize & 0xF] = val;
     }
 }
+
+impl_state!(APU {
+    enabled,
+    pan,
+    wave_ram
+});
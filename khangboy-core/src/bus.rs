@@ -0,0 +1,161 @@
+use crate::components::Components;
+
+// Everything that the CPU can access
+// Components implements this, but anything else (like a flat test bus, a GBS player, or an
+// instrumented wrapper) can be swapped in
+// The CPU is generic over this instead of using a trait object so each bus gets its own
+// monomorphized copy of the opcode table
+pub trait Bus {
+    // Processes one M-cycle
+    fn tick(&mut self);
+
+    // Reads a byte from an address without ticking
+    fn read_passive(&mut self, addr: u16) -> u8;
+
+    // Writes a byte to an address without ticking
+    fn write_passive(&mut self, addr: u16, val: u8);
+
+    // Gets the number of M-cycles processed so far
+    fn cycle(&self) -> u64;
+
+    // Gets the interrupts that are both requested (IF) and enabled (IE)
+    // Bit 0: VBlank, bit 1: STAT, bit 2: Timer, bit 3: Serial, bit 4: Joypad
+    fn pending_interrupts(&self) -> u8;
+
+    // Clears an interrupt's request flag once the CPU has started servicing it
+    fn acknowledge_interrupt(&mut self, bit: u8);
+
+    // Called when the CPU enters or leaves a routine, for keeping track of the call stack
+    // This does nothing unless a bus wants to know about it
+    #[inline]
+    fn call_event(&mut self, _event: CallEvent) {}
+
+    // Ticks by one M-cycle, then reads a byte from an address
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.read_passive(addr)
+    }
+
+    // Ticks by one M-cycle, then reads a byte at PC
+    // opcode is set for the first byte of an instruction and cleared for everything after it
+    // This is just a normal read unless a bus wants to know what's being executed
+    #[inline]
+    fn fetch(&mut self, addr: u16, _opcode: bool) -> u8 {
+        self.read(addr)
+    }
+
+    // Ticks by one M-cycle, then writes a byte to an address
+    #[inline]
+    fn write(&mut self, addr: u16, val: u8) {
+        self.tick();
+        self.write_passive(addr, val)
+    }
+
+    // Ticks by one M-cycle where the CPU's 16-bit increment/decrement unit changes a register
+    // holding addr without accessing memory (e.g. INC rr or the start of PUSH)
+    // This is just a normal tick unless a bus wants to emulate the DMG's OAM corruption bug
+    #[inline]
+    fn tick_idu(&mut self, _addr: u16) {
+        self.tick()
+    }
+
+    // Ticks by one M-cycle, then reads a byte from an address while the increment/decrement unit
+    // changes the register holding it (e.g. LD A,(HL+) or POP)
+    // This is just a normal read unless a bus wants to emulate the DMG's OAM corruption bug
+    #[inline]
+    fn read_idu(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+}
+
+// Control flow that changes the call stack
+// sp is where the return address was pushed to or popped from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallEvent {
+    // CALL or RST from the instruction at from
+    Call { from: u16, to: u16, sp: u16 },
+    // An interrupt was dispatched while about to execute the instruction at from
+    Interrupt { from: u16, to: u16, sp: u16 },
+    // RET or RETI
+    Return { sp: u16 },
+}
+
+// Gets told about what the CPU does through an ObservedBus (e.g. for debuggers and profilers)
+// Hooks are called before the access goes through, and do nothing unless implemented
+pub trait BusObserver {
+    // Called for reads, including ones made while the increment/decrement unit is busy
+    #[inline]
+    fn on_read(&mut self, _com: &Components, _addr: u16) {}
+
+    // Called for bytes read at PC, which aren't counted as normal reads
+    #[inline]
+    fn on_fetch(&mut self, _com: &Components, _addr: u16, _opcode: bool) {}
+
+    #[inline]
+    fn on_write(&mut self, _com: &Components, _addr: u16, _val: u8) {}
+
+    #[inline]
+    fn on_call_event(&mut self, _com: &Components, _event: CallEvent) {}
+}
+
+// Passes everything through to Components, but lets an observer see each access first
+pub struct ObservedBus<'a, O> {
+    pub com: &'a mut Components,
+    pub observer: &'a mut O,
+}
+
+impl<O: BusObserver> Bus for ObservedBus<'_, O> {
+    fn tick(&mut self) {
+        self.com.tick()
+    }
+
+    fn read_passive(&mut self, addr: u16) -> u8 {
+        self.com.read_passive(addr)
+    }
+
+    fn write_passive(&mut self, addr: u16, val: u8) {
+        self.com.write_passive(addr, val)
+    }
+
+    fn cycle(&self) -> u64 {
+        self.com.cycle()
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.com.pending_interrupts()
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.com.acknowledge_interrupt(bit)
+    }
+
+    fn call_event(&mut self, event: CallEvent) {
+        self.observer.on_call_event(self.com, event);
+        self.com.call_event(event)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.observer.on_read(self.com, addr);
+        self.com.read(addr)
+    }
+
+    fn fetch(&mut self, addr: u16, opcode: bool) -> u8 {
+        self.observer.on_fetch(self.com, addr, opcode);
+        self.com.fetch(addr, opcode)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.observer.on_write(self.com, addr, val);
+        self.com.write(addr, val)
+    }
+
+    fn tick_idu(&mut self, addr: u16) {
+        self.com.tick_idu(addr)
+    }
+
+    fn read_idu(&mut self, addr: u16) -> u8 {
+        self.observer.on_read(self.com, addr);
+        self.com.read_idu(addr)
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use crate::{
+    bus::{BusObserver, ObservedBus},
+    components::Components,
+    Gameboy,
+};
+
+// Code/data log, which records how every byte of the ROM has been accessed
+// This is saved as one flag byte per ROM byte
+// The idea is the same as FCEUX's CDL files, but the bits are specific to khangboy:
+//   bit 0: executed as code (opcode or operand)
+//   bit 1: read as data
+//   bit 2: executed as the first byte of an instruction
+// Offsets are into the ROM file, so the same address in different banks gets logged separately
+// The Gameboy has to be stepped through this for anything to be recorded
+pub struct CodeDataLog {
+    pub flags: Vec<u8>,
+}
+
+pub const CDL_CODE: u8 = 1 << 0;
+pub const CDL_DATA: u8 = 1 << 1;
+pub const CDL_OPCODE: u8 = 1 << 2;
+
+impl CodeDataLog {
+    pub fn new(gb: &Gameboy) -> Self {
+        Self {
+            flags: vec![0; gb.components.rom_size()],
+        }
+    }
+
+    // Loads a log saved by save, which has to be for a ROM of the same size
+    pub fn load(gb: &Gameboy, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let flags = std::fs::read(path)?;
+        if flags.len() != gb.components.rom_size() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "CDL file doesn't match the ROM's size",
+            ));
+        }
+        Ok(Self { flags })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, &self.flags)
+    }
+
+    // Steps the CPU by one instruction while logging ROM accesses
+    pub fn step(&mut self, gb: &mut Gameboy) -> u64 {
+        let mut bus = ObservedBus {
+            com: &mut gb.components,
+            observer: self,
+        };
+        gb.cpu.step(&mut bus)
+    }
+
+    // Runs for AT LEAST n cycles
+    // Actual cycle count is returned
+    pub fn run(&mut self, gb: &mut Gameboy, cycles: u64) -> u64 {
+        let mut executed = 0;
+        while executed < cycles {
+            executed += self.step(gb);
+        }
+        executed
+    }
+
+    // Counts how many bytes have been logged as code and as data
+    pub fn coverage(&self) -> (usize, usize) {
+        let code = self.flags.iter().filter(|&&x| x & CDL_CODE != 0).count();
+        let data = self.flags.iter().filter(|&&x| x & CDL_DATA != 0).count();
+        (code, data)
+    }
+
+    fn log(&mut self, com: &Components, addr: u16, flags: u8) {
+        if let Some(offset) = com.rom_offset(addr) {
+            self.flags[offset] |= flags;
+        }
+    }
+}
+
+// Only CPU reads from ROM are logged
+// OAM DMA doesn't go through the CPU, so ROM read by it isn't logged
+impl BusObserver for CodeDataLog {
+    fn on_read(&mut self, com: &Components, addr: u16) {
+        self.log(com, addr, CDL_DATA);
+    }
+
+    fn on_fetch(&mut self, com: &Components, addr: u16, opcode: bool) {
+        let flags = if opcode {
+            CDL_CODE | CDL_OPCODE
+        } else {
+            CDL_CODE
+        };
+        self.log(com, addr, flags);
+    }
+}
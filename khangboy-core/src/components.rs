@@ -1,4 +1,24 @@
-use crate::{apu::APU, joypad::Joypad, ppu::PPU, rom::ROM, serial::Serial, timer::Timer};
+use crate::{
+    apu::APU,
+    bus::Bus,
+    joypad::Joypad,
+    ppu::PPU,
+    rom::ROM,
+    savestate::{self, impl_state},
+    serial::Serial,
+    timer::Timer,
+    util::BitIndex,
+};
+
+// How many dots into an M-cycle the PPU sees a write to one of its registers
+// Reads still happen at the end of the M-cycle, but writes land partway through, so raster
+// effects line up with the pixel that was being drawn when the write happened
+const PPU_WRITE_DOTS: u8 = 2;
+
+// Registers that the PPU reads while drawing, so writes to them have to land on the right dot
+fn is_ppu_register(addr: u16) -> bool {
+    matches!(addr, 0xFF40..=0xFF43 | 0xFF45 | 0xFF47..=0xFF4B)
+}
 
 // Holds everything that the CPU has to interact with
 // Also gets ticked by the CPU struct
@@ -8,7 +28,7 @@ pub struct Components {
     pub ppu: PPU,
     apu: APU,
     timer: Timer,
-    serial: Serial,
+    pub serial: Serial,
     pub joypad: Joypad,
     wram: [u8; 0x2000],
     hram: [u8; 0x80],
@@ -18,10 +38,14 @@ pub struct Components {
     pub interrupt_enable: u8,
 
     pub cycle: u64,
+
+    // Identifies the ROM in save states
+    rom_hash: u64,
 }
 
 impl Components {
     pub fn new(rom: Box<dyn ROM>) -> Self {
+        let rom_hash = savestate::hash(rom.data());
         Self {
             rom,
             bootrom: Default::default(),
@@ -40,67 +64,45 @@ impl Components {
             interrupt_enable: 0,
 
             cycle: 0,
+
+            rom_hash,
         }
     }
 
-    // Processes one M-cycle/four T-cycles
-    pub fn tick(&mut self) {
-        // TODO: What order is this supposed to be in? Does it even matter?
-        self.interrupt_flag |= (self.timer.tick() as u8) << 2;
-
-        let (vblank, stat) = self.ppu.tick();
-        self.interrupt_flag |= vblank as u8;
-        self.interrupt_flag |= (stat as u8) << 1;
-        if self.ppu.oam_dma_running {
-            // TODO: Emulate source area inaccessibility during DMA
-            let data = self
-                .read_passive(((self.ppu.oam_dma_src as u16) << 8) | self.ppu.oam_dma_idx as u16);
-            self.ppu.write_oam(self.ppu.oam_dma_idx as u16, data);
-            self.ppu.oam_dma_idx += 1;
-            if self.ppu.oam_dma_idx == 160 {
-                self.ppu.oam_dma_running = false;
-            }
+    // Gets the bank mapped to an address, which is always 0 outside of cart ROM and RAM
+    pub fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.rom.bank(addr),
+            _ => 0,
         }
+    }
 
-        self.interrupt_flag |= (self.joypad.tick() as u8) << 4;
+    pub fn rom_size(&self) -> usize {
+        self.rom.size()
+    }
 
-        self.apu.tick();
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
 
-        self.cycle += 1;
+    pub fn cart_ram(&self) -> &[u8] {
+        self.rom.ram()
     }
 
-    // Ticks components by one M-cycle, then reads a byte from an address
-    pub fn read(&mut self, addr: u16) -> u8 {
-        self.tick();
-        self.read_passive(addr)
+    pub fn cart_ram_mut(&mut self) -> &mut [u8] {
+        self.rom.ram_mut()
     }
 
-    // Reads a byte from an address without ticking
-    pub fn read_passive(&mut self, addr: u16) -> u8 {
+    // Gets the offset into the ROM file that an address is currently mapped to
+    // Returns None for anything outside of cart ROM, including the bootrom while it's mapped
+    pub fn rom_offset(&self, addr: u16) -> Option<usize> {
         match addr {
-            // Cart ROM/Bootrom
+            0x0000..=0x00FF if !self.bootrom_disabled => None,
             0x0000..=0x7FFF => {
-                if addr < 0x100 && !self.bootrom_disabled {
-                    self.bootrom.read(addr as u8)
-                } else {
-                    self.rom.read_rom(addr)
-                }
+                let offset = self.rom.bank(addr) as usize * 0x4000 + (addr as usize & 0x3FFF);
+                Some(offset % self.rom.size())
             }
-            // VRAM
-            0x8000..=0x9FFF => self.ppu.read_vram(addr),
-            // Cart RAM
-            0xA000..=0xBFFF => self.rom.read_ram(addr),
-            // WRAM and Echo RAM
-            0xC000..=0xFDFF => self.wram[addr as usize & 0x1FFF],
-            // OAM
-            0xFE00..=0xFEFF => self.ppu.read_oam(addr),
-            // I/O region
-            0xFF00..=0xFF7F => self.read_io(addr),
-            // HRAM
-            0xFF80..=0xFFFE => self.hram[addr as usize & 0x7F],
-            // Interrupt enable
-            // TODO: Do invalid interrupt bits get set/cleared?
-            0xFFFF => self.interrupt_enable,
+            _ => None,
         }
     }
 
@@ -158,34 +160,102 @@ impl Components {
         }
     }
 
-    // Ticks components by one M-cycle, then writes a byte to an address
-    pub fn write(&mut self, addr: u16, val: u8) {
-        self.tick();
-        self.write_passive(addr, val)
+    // Processes one M-cycle, doing a memory access after the PPU has run for ppu_dots dots
+    fn tick_with(&mut self, ppu_dots: u8, access: impl FnOnce(&mut Self)) {
+        // TODO: What order is this supposed to be in? Does it even matter?
+        self.interrupt_flag |= (self.timer.tick() as u8) << 2;
+
+        self.tick_ppu(ppu_dots);
+        access(self);
+        self.tick_ppu(4 - ppu_dots);
+
+        self.tick_oam_dma();
+
+        self.interrupt_flag |= (self.serial.tick() as u8) << 3;
+
+        self.interrupt_flag |= (self.joypad.tick() as u8) << 4;
+
+        self.apu.tick();
+
+        self.cycle += 1;
     }
 
-    // Writes a byte to an address without ticking
-    pub fn write_passive(&mut self, addr: u16, val: u8) {
+    // Copies one byte per M-cycle once a transfer has started, taking 160 M-cycles in total
+    fn tick_oam_dma(&mut self) {
+        // Transfers start 2 M-cycles after the write, with a running transfer continuing until then
+        if self.ppu.oam_dma_start_delay > 0 {
+            self.ppu.oam_dma_start_delay -= 1;
+            if self.ppu.oam_dma_start_delay == 0 {
+                self.ppu.oam_dma_running = true;
+                self.ppu.oam_dma_page = self.ppu.oam_dma_src;
+                self.ppu.oam_dma_idx = 0;
+            }
+        }
+
+        if self.ppu.oam_dma_running {
+            // The bus stays in use for the M-cycle that copied the last byte
+            if self.ppu.oam_dma_idx == 160 {
+                self.ppu.oam_dma_running = false;
+                return;
+            }
+            let mut addr = u16::from_be_bytes([self.ppu.oam_dma_page, self.ppu.oam_dma_idx]);
+            // DMA can't see OAM or I/O, so anything past WRAM reads from WRAM instead
+            if addr >= 0xE000 {
+                addr -= 0x2000;
+            }
+            let data = self.read_passive(addr);
+            self.ppu.write_oam_dma(self.ppu.oam_dma_idx, data);
+            self.ppu.oam_dma_byte = data;
+            self.ppu.oam_dma_idx += 1;
+        }
+    }
+
+    // Returns what the CPU sees at addr if a running OAM DMA transfer is using the same bus
+    // DMA either uses the VRAM bus or the external bus (cart & WRAM), and OAM is blocked entirely,
+    // but HRAM, I/O and IE are always accessible
+    fn oam_dma_conflict(&self, addr: u16) -> Option<u8> {
+        if !self.ppu.oam_dma_running {
+            return None;
+        }
+        let is_vram = |page: u8| (0x80..=0x9F).contains(&page);
         match addr {
-            // ROM banks/Bootrom
-            0x0000..=0x7FFF => self.rom.write_rom(addr, val),
-            // VRAM
-            0x8000..=0x9FFF => self.ppu.write_vram(addr, val),
-            // Cart RAM
-            0xA000..=0xBFFF => self.rom.write_ram(addr, val),
-            // WRAM and Echo RAM
-            0xC000..=0xFDFF => self.wram[addr as usize & 0x1FFF] = val,
-            // OAM
-            0xFE00..=0xFEFF => self.ppu.write_oam(addr, val),
-            // I/O region
-            0xFF00..=0xFF7F => self.write_io(addr, val),
-            // HRAM
-            0xFF80..=0xFFFE => self.hram[addr as usize & 0x7F] = val,
-            // Interrupt enable
-            0xFFFF => self.interrupt_enable = val,
+            0xFE00..=0xFEFF => Some(0xFF),
+            0xFF00..=0xFFFF => None,
+            _ if is_vram((addr >> 8) as u8) == is_vram(self.ppu.oam_dma_page) => {
+                Some(self.ppu.oam_dma_byte)
+            }
+            _ => None,
         }
     }
 
+    // Checks if the CPU putting addr on the bus can trigger the OAM corruption bug
+    // This includes the unusable area after OAM, but not while DMA has taken over OAM
+    fn is_oam_bug_addr(&self, addr: u16) -> bool {
+        (0xFE00..=0xFEFF).contains(&addr) && !self.ppu.oam_dma_running
+    }
+
+    // Handles a read by the CPU after the M-cycle has been ticked
+    // idu is set if the register holding addr is being incremented or decremented
+    fn read_cpu(&mut self, addr: u16, idu: bool) -> u8 {
+        if self.is_oam_bug_addr(addr) {
+            if idu {
+                self.ppu.oam_bug_read_idu();
+            } else {
+                self.ppu.oam_bug_read();
+            }
+        }
+        match self.oam_dma_conflict(addr) {
+            Some(val) => val,
+            None => self.read_passive(addr),
+        }
+    }
+
+    fn tick_ppu(&mut self, dots: u8) {
+        let (vblank, stat) = self.ppu.tick_dots(dots);
+        self.interrupt_flag |= vblank as u8;
+        self.interrupt_flag |= (stat as u8) << 1;
+    }
+
     // Handles I/O region (0xFFxx) writes
     fn write_io(&mut self, addr: u16, val: u8) {
         match addr as u8 {
@@ -284,6 +354,124 @@ impl Components {
     }
 }
 
+impl Bus for Components {
+    // Processes one M-cycle/four T-cycles
+    fn tick(&mut self) {
+        self.tick_with(4, |_| ());
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.read_cpu(addr, false)
+    }
+
+    fn read_idu(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.read_cpu(addr, true)
+    }
+
+    fn tick_idu(&mut self, addr: u16) {
+        self.tick();
+        if self.is_oam_bug_addr(addr) {
+            self.ppu.oam_bug_write();
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if is_ppu_register(addr) {
+            self.tick_with(PPU_WRITE_DOTS, |com| com.write_passive(addr, val));
+        } else {
+            self.tick();
+            if self.is_oam_bug_addr(addr) {
+                self.ppu.oam_bug_write();
+            }
+            // Writes to a bus that DMA is using get lost
+            if self.oam_dma_conflict(addr).is_none() {
+                self.write_passive(addr, val);
+            }
+        }
+    }
+
+    // Reads a byte from an address without ticking
+    fn read_passive(&mut self, addr: u16) -> u8 {
+        match addr {
+            // Cart ROM/Bootrom
+            0x0000..=0x7FFF => {
+                if addr < 0x100 && !self.bootrom_disabled {
+                    self.bootrom.read(addr as u8)
+                } else {
+                    self.rom.read_rom(addr)
+                }
+            }
+            // VRAM
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            // Cart RAM
+            0xA000..=0xBFFF => self.rom.read_ram(addr),
+            // WRAM and Echo RAM
+            0xC000..=0xFDFF => self.wram[addr as usize & 0x1FFF],
+            // OAM
+            0xFE00..=0xFEFF => self.ppu.read_oam(addr),
+            // I/O region
+            0xFF00..=0xFF7F => self.read_io(addr),
+            // HRAM
+            0xFF80..=0xFFFE => self.hram[addr as usize & 0x7F],
+            // Interrupt enable
+            // TODO: Do invalid interrupt bits get set/cleared?
+            0xFFFF => self.interrupt_enable,
+        }
+    }
+
+    // Writes a byte to an address without ticking
+    fn write_passive(&mut self, addr: u16, val: u8) {
+        match addr {
+            // ROM banks/Bootrom
+            0x0000..=0x7FFF => self.rom.write_rom(addr, val),
+            // VRAM
+            0x8000..=0x9FFF => self.ppu.write_vram(addr, val),
+            // Cart RAM
+            0xA000..=0xBFFF => self.rom.write_ram(addr, val),
+            // WRAM and Echo RAM
+            0xC000..=0xFDFF => self.wram[addr as usize & 0x1FFF] = val,
+            // OAM
+            0xFE00..=0xFEFF => self.ppu.write_oam(addr, val),
+            // I/O region
+            0xFF00..=0xFF7F => self.write_io(addr, val),
+            // HRAM
+            0xFF80..=0xFFFE => self.hram[addr as usize & 0x7F] = val,
+            // Interrupt enable
+            0xFFFF => self.interrupt_enable = val,
+        }
+    }
+
+    fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flag & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.interrupt_flag = self.interrupt_flag.set(bit, false);
+    }
+}
+
+// The bootrom never changes, so it isn't included
+impl_state!(Components {
+    rom,
+    ppu,
+    apu,
+    timer,
+    serial,
+    joypad,
+    wram,
+    hram,
+    bootrom_disabled,
+    interrupt_flag,
+    interrupt_enable,
+    cycle
+});
+
 // Holds the DMG bootrom
 // TODO: Load this from a file
 struct Bootrom {
@@ -1,4 +1,8 @@
-use crate::{components::Components, util::BitIndex};
+use crate::{
+    bus::{Bus, CallEvent},
+    savestate::impl_state,
+    util::BitIndex,
+};
 
 #[derive(Default, Debug)]
 pub struct CPU {
@@ -10,10 +14,9 @@ pub struct CPU {
     pub f: u8, // Flags
     pub h: u8,
     pub l: u8,
-    ime: bool,        // Interrupts
+    pub ime: bool,    // Interrupts
     ime_queued: bool, // The effects of EI are delayed by one instruction
     halted: bool,
-    halt_bug: bool,
 
     pub sp: u16,
     pub pc: u16,
@@ -69,7 +72,8 @@ impl Reg16 {
     }
 }
 
-enum Reg8<'a> {
+#[derive(Clone, Copy)]
+enum Reg8 {
     A,
     B,
     C,
@@ -78,12 +82,12 @@ enum Reg8<'a> {
     //F, // Never gets used?
     H,
     L,
-    HLPtr(&'a mut Components),
+    HLPtr,
 }
 
-impl Reg8<'_> {
+impl Reg8 {
     #[inline]
-    pub fn read(&mut self, cpu: &mut CPU) -> u8 {
+    pub fn read<B: Bus>(&self, cpu: &mut CPU, com: &mut B) -> u8 {
         match self {
             Reg8::A => cpu.a,
             Reg8::B => cpu.b,
@@ -92,7 +96,7 @@ impl Reg8<'_> {
             Reg8::E => cpu.e,
             Reg8::H => cpu.h,
             Reg8::L => cpu.l,
-            Reg8::HLPtr(com) => {
+            Reg8::HLPtr => {
                 let hl = Reg16::HL.read(cpu);
                 cpu.read8(com, hl)
             }
@@ -100,7 +104,7 @@ impl Reg8<'_> {
     }
 
     #[inline]
-    pub fn write(&mut self, cpu: &mut CPU, val: u8) {
+    pub fn write<B: Bus>(&self, cpu: &mut CPU, com: &mut B, val: u8) {
         match self {
             Reg8::A => cpu.a = val,
             Reg8::B => cpu.b = val,
@@ -109,7 +113,7 @@ impl Reg8<'_> {
             Reg8::E => cpu.e = val,
             Reg8::H => cpu.h = val,
             Reg8::L => cpu.l = val,
-            Reg8::HLPtr(com) => {
+            Reg8::HLPtr => {
                 let hl = Reg16::HL.read(cpu);
                 cpu.write8(com, hl, val);
             }
@@ -122,39 +126,51 @@ impl CPU {
         Default::default()
     }
 
+    // Gets the opcode that will be executed on the next step
+    pub fn next_opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    // Gets the address of the instruction that will be executed on the next step
+    // PC is already past it since its opcode gets fetched early
+    pub fn instruction_addr(&self) -> u16 {
+        self.pc.wrapping_sub(1)
+    }
+
+    // Moves execution to an address, fetching the opcode there without ticking
+    pub fn set_instruction_addr<B: Bus>(&mut self, com: &mut B, addr: u16) {
+        self.opcode = com.read_passive(addr);
+        self.pc = addr.wrapping_add(1);
+    }
+
     // Steps by one instruction
     // Also ticks every component accordingly depending on the timing
     // M-cycle (4 T-cycles) granularity, but most other GB emulators have that too
-    pub fn step(&mut self, com: &mut Components) -> u64 {
-        let start_cycle = com.cycle;
-
-        // Handle interrupts
-        let interrupts = com.interrupt_enable & com.interrupt_flag;
-        if self.ime && interrupts != 0 {
-            for i in 0..=5 {
-                if interrupts.test(i) {
-                    com.interrupt_flag = com.interrupt_flag.set(i, false);
-                    self.ime = false;
-                    self.halted = false;
-                    self.run_cycle(com);
-                    self.push_val(com, self.pc.wrapping_sub(1));
-                    self.pc = (0x40 + i * 8) as u16;
-                    self.opcode = self.fetch8(com);
-                    break;
-                }
-            }
-        }
+    pub fn step<B: Bus>(&mut self, com: &mut B) -> u64 {
+        let start_cycle = com.cycle();
 
         // Handle halted state
+        // The CPU wakes up once an interrupt is pending, even if IME isn't set
         if self.halted {
-            if interrupts == 0 {
+            if com.pending_interrupts() == 0 {
                 self.run_cycle(com);
-                return com.cycle - start_cycle;
+                return com.cycle() - start_cycle;
             }
             self.halted = false;
+
+            // Waking up takes an extra M-cycle before an interrupt can be dispatched
+            if self.ime {
+                self.run_cycle(com);
+            }
+        }
+
+        // Handle interrupts
+        if self.ime && com.pending_interrupts() != 0 {
+            self.dispatch_interrupt(com);
         }
 
         // The effects of EI are delayed by one instruction
+        let ime_enabled_now = self.ime_queued;
         if self.ime_queued {
             self.ime = true;
             self.ime_queued = false;
@@ -163,100 +179,154 @@ impl CPU {
         // Run opcode
         // This massive 256-case match statement is generated at compile-time
         // See build.rs
+        let opcode = self.opcode;
+        let addr = self.instruction_addr();
+        let sp = self.sp;
         include!("opcodes.inl");
 
+        // Report calls and returns, using SP to tell if conditional ones were taken
+        let is_call = opcode == 0xCD || opcode & 0xE7 == 0xC4 || opcode & 0xC7 == 0xC7;
+        let is_ret = opcode == 0xC9 || opcode == 0xD9 || opcode & 0xE7 == 0xC0;
+        if is_call && self.sp == sp.wrapping_sub(2) {
+            com.call_event(CallEvent::Call {
+                from: addr,
+                to: self.pc,
+                sp: self.sp,
+            });
+        } else if is_ret && self.sp == sp.wrapping_add(2) {
+            com.call_event(CallEvent::Return { sp });
+        }
+
         //println!("{self:?}");
 
         // Fetch the next opcode
         // This happens in the same M-cycle as the last execution cycle
-        if !self.halt_bug {
-            self.opcode = self.fetch8(com);
-        }
-        self.halt_bug = false;
+        self.opcode = self.fetch_opcode(com);
 
-        if self.halted && !self.ime && (com.interrupt_enable & com.interrupt_flag) != 0 {
-            self.halt_bug = true;
+        // HALT exits immediately if an interrupt is already pending
+        // If IME wasn't already set, PC fails to increment after that fetch (the halt bug),
+        // so the byte after HALT gets read twice
+        // With EI right before HALT, this makes the interrupt return to the HALT itself
+        if self.halted && com.pending_interrupts() != 0 {
             self.halted = false;
+            if !self.ime || ime_enabled_now {
+                self.pc = self.pc.wrapping_sub(1);
+            }
         }
 
-        com.cycle - start_cycle
+        com.cycle() - start_cycle
+    }
+
+    // Services the highest priority pending interrupt, which takes 5 M-cycles
+    // See https://gbdev.io/pandocs/Interrupts.html#interrupt-handling
+    fn dispatch_interrupt<B: Bus>(&mut self, com: &mut B) {
+        self.ime = false;
+
+        // The opcode that was already fetched gets thrown away
+        self.pc = self.pc.wrapping_sub(1);
+        let return_addr = self.pc;
+        self.run_cycle(com);
+        com.tick_idu(self.sp);
+
+        // The interrupt to service is only decided after the high byte of PC is pushed
+        // If that write lands on IE (SP = 0x0000), it can switch to a different interrupt or
+        // cancel the dispatch entirely, which jumps to 0x0000 instead
+        self.sp = self.sp.wrapping_sub(1);
+        self.write8(com, self.sp, (self.pc >> 8) as u8);
+        let interrupts = com.pending_interrupts();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write8(com, self.sp, self.pc as u8);
+
+        self.pc = match (0..5).find(|&i| interrupts.test(i)) {
+            Some(i) => {
+                com.acknowledge_interrupt(i);
+                0x40 + i as u16 * 8
+            }
+            None => 0x0000,
+        };
+        com.call_event(CallEvent::Interrupt {
+            from: return_addr,
+            to: self.pc,
+            sp: self.sp,
+        });
+        self.opcode = self.fetch_opcode(com);
     }
 
     // Handles 0xCB prefix bit arithmetic opcodes
-    fn handle_cb(&mut self, com: &mut Components) {
+    fn handle_cb<B: Bus>(&mut self, com: &mut B) {
         let opcode = self.fetch8(com);
-        let mut target = match opcode & 7 {
+        let target = match opcode & 7 {
             0x0 => Reg8::B,
             0x1 => Reg8::C,
             0x2 => Reg8::D,
             0x3 => Reg8::E,
             0x4 => Reg8::H,
             0x5 => Reg8::L,
-            0x6 => Reg8::HLPtr(com),
+            0x6 => Reg8::HLPtr,
             0x7 => Reg8::A,
             _ => unreachable!(),
         };
         match opcode & 0xF8 {
             // RLC n
             0x00 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = val.rotate_left(1);
                 self.set_flags(res == 0, false, false, (res & 1) != 0);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // RRC n
             0x08 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = val.rotate_right(1);
                 self.set_flags(res == 0, false, false, (res & 0x80) != 0);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // RL r
             0x10 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = (val << 1) | ((self.get_c() as u8) & 1);
                 self.set_flags(res == 0, false, false, (val & 0x80) != 0);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // RR r
             0x18 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = (val >> 1) | ((self.get_c() as u8) << 7);
                 self.set_flags(res == 0, false, false, (val & 1) != 0);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // SLA r
             0x20 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = val << 1;
                 self.set_flags(res == 0, false, false, (val & 0x80) != 0);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // SRA r
             0x28 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = (val as i8) >> 1;
                 self.set_flags(res == 0, false, false, (val & 1) != 0);
-                target.write(self, res as u8);
+                target.write(self, com, res as u8);
             }
             // SWAP r
             0x30 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = (val & 0xF0) >> 4 | (val & 0xF) << 4;
                 self.set_flags(res == 0, false, false, false);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // SRL r
             0x38 => {
-                let val = target.read(self);
+                let val = target.read(self, com);
                 let res = val >> 1;
                 self.set_flags(res == 0, false, false, (val & 1) != 0);
-                target.write(self, res);
+                target.write(self, com, res);
             }
             // BIT n,r
             0x40..=0x78 => {
                 let n = (opcode >> 3) & 7;
-                let val = target.read(self);
+                let val = target.read(self, com);
                 self.set_z(!val.test(n));
                 self.set_n(false);
                 self.set_h(true);
@@ -264,14 +334,14 @@ impl CPU {
             // RES n,r
             0x80..=0xB8 => {
                 let n = (opcode >> 3) & 7;
-                let val = target.read(self);
-                target.write(self, val & !(1 << n));
+                let val = target.read(self, com);
+                target.write(self, com, val & !(1 << n));
             }
             // SET n,r
             0xC0..=0xF8 => {
                 let n = (opcode >> 3) & 7;
-                let val = target.read(self);
-                target.write(self, val | (1 << n));
+                let val = target.read(self, com);
+                target.write(self, com, val | (1 << n));
             }
             _ => unimplemented!("Unhandled 0xCB opcode 0x{opcode:02x}"),
         }
@@ -279,43 +349,55 @@ impl CPU {
 
     // Runs one M-cycle
     #[inline]
-    fn run_cycle(&mut self, com: &mut Components) {
+    fn run_cycle<B: Bus>(&mut self, com: &mut B) {
         com.tick();
     }
 
     // Reads an 8-bit value from an address
     #[inline]
-    fn read8(&mut self, com: &mut Components, addr: u16) -> u8 {
+    fn read8<B: Bus>(&mut self, com: &mut B, addr: u16) -> u8 {
         com.read(addr)
     }
 
+    // Reads an 8-bit value from an address held by a register that gets incremented or
+    // decremented in the same M-cycle
+    #[inline]
+    fn read8_idu<B: Bus>(&mut self, com: &mut B, addr: u16) -> u8 {
+        com.read_idu(addr)
+    }
+
     // Writes an 8-bit value to an address
     #[inline]
-    fn write8(&mut self, com: &mut Components, addr: u16, val: u8) {
+    fn write8<B: Bus>(&mut self, com: &mut B, addr: u16, val: u8) {
         com.write(addr, val)
     }
 
     // Reads a little-endian 16-bit integer from an address
     #[inline]
-    pub fn read16(&mut self, com: &mut Components, addr: u16) -> u16 {
+    pub fn read16<B: Bus>(&mut self, com: &mut B, addr: u16) -> u16 {
         self.read8(com, addr) as u16 | (self.read8(com, addr.wrapping_add(1)) as u16) << 8
     }
 
+    // Reads the opcode at PC and increments it
+    #[inline]
+    fn fetch_opcode<B: Bus>(&mut self, com: &mut B) -> u8 {
+        let ret = com.fetch(self.pc, true);
+        self.pc = self.pc.wrapping_add(1);
+        ret
+    }
+
     // Reads the byte at PC and increments it
     #[inline]
-    fn fetch8(&mut self, com: &mut Components) -> u8 {
-        let ret = self.read8(com, self.pc);
+    fn fetch8<B: Bus>(&mut self, com: &mut B) -> u8 {
+        let ret = com.fetch(self.pc, false);
         self.pc = self.pc.wrapping_add(1);
         ret
     }
 
     // Reads 2 bytes at PC and increments it
-    // TODO: This should probably be more granular, but does it really matter?
     #[inline]
-    fn fetch16(&mut self, com: &mut Components) -> u16 {
-        let ret = self.read16(com, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        ret
+    fn fetch16<B: Bus>(&mut self, com: &mut B) -> u16 {
+        self.fetch8(com) as u16 | (self.fetch8(com) as u16) << 8
     }
 
     // Helper function to set all 4 flags at once
@@ -377,8 +459,8 @@ impl CPU {
 
     // Pushes a 16-bit integer onto the stack
     #[inline]
-    fn push_val(&mut self, com: &mut Components, val: u16) {
-        self.run_cycle(com);
+    fn push_val<B: Bus>(&mut self, com: &mut B, val: u16) {
+        com.tick_idu(self.sp);
         self.write8(com, self.sp.wrapping_sub(1), (val >> 8) as u8);
         self.write8(com, self.sp.wrapping_sub(2), val as u8);
         self.sp = self.sp.wrapping_sub(2);
@@ -386,21 +468,21 @@ impl CPU {
 
     // Handles LD r16, d16
     #[inline]
-    fn ld_r16_d16(&mut self, com: &mut Components, target: Reg16) {
+    fn ld_r16_d16<B: Bus>(&mut self, com: &mut B, target: Reg16) {
         let imm = self.fetch16(com);
         target.write(self, imm);
     }
 
     // Handles LD r8, d8
-    fn ld_r8_d8(&mut self, imm: u8, mut target: Reg8) {
-        target.write(self, imm);
+    fn ld_r8_d8<B: Bus>(&mut self, com: &mut B, imm: u8, target: Reg8) {
+        target.write(self, com, imm);
     }
 
     // Handles the INC r8 instruction and its flags
     #[inline]
-    fn inc_r8(&mut self, mut target: Reg8) {
-        let res = target.read(self).wrapping_add(1);
-        target.write(self, res);
+    fn inc_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let res = target.read(self, com).wrapping_add(1);
+        target.write(self, com, res);
         self.set_z(res == 0);
         self.set_n(false);
         self.set_h(res & 0xF == 0);
@@ -408,9 +490,9 @@ impl CPU {
 
     // Handles the DEC r8 instruction and its flags
     #[inline]
-    fn dec_r8(&mut self, mut target: Reg8) {
-        let res = target.read(self).wrapping_sub(1);
-        target.write(self, res);
+    fn dec_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let res = target.read(self, com).wrapping_sub(1);
+        target.write(self, com, res);
         self.set_z(res == 0);
         self.set_n(true);
         self.set_h(res & 0xF == 0xF);
@@ -418,57 +500,59 @@ impl CPU {
 
     // Handles the INC r16 instruction and its flags
     #[inline]
-    fn inc_r16(&mut self, com: &mut Components, target: Reg16) {
-        self.run_cycle(com);
+    fn inc_r16<B: Bus>(&mut self, com: &mut B, target: Reg16) {
+        com.tick_idu(target.read(self));
         target.write(self, target.read(self).wrapping_add(1));
     }
 
     // Handles the DEC r16 instruction and its flags
     #[inline]
-    fn dec_r16(&mut self, com: &mut Components, target: Reg16) {
-        self.run_cycle(com);
+    fn dec_r16<B: Bus>(&mut self, com: &mut B, target: Reg16) {
+        com.tick_idu(target.read(self));
         target.write(self, target.read(self).wrapping_sub(1));
     }
 
     // Handles the XOR r8 instruction and its flags
     #[inline]
-    fn xor_r8(&mut self, mut target: Reg8) {
-        self.a ^= target.read(self);
+    fn xor_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        self.a ^= target.read(self, com);
         self.set_flags(self.a == 0, false, false, false);
     }
 
     // Handles the OR r8 instruction and its flags
     #[inline]
-    fn or_r8(&mut self, mut target: Reg8) {
-        self.a |= target.read(self);
+    fn or_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        self.a |= target.read(self, com);
         self.set_flags(self.a == 0, false, false, false);
     }
 
     // Handles the RST n instruction
     #[inline]
-    fn rst_n(&mut self, com: &mut Components, addr: u16) {
+    fn rst_n<B: Bus>(&mut self, com: &mut B, addr: u16) {
         self.push_val(com, self.pc);
         self.pc = addr;
     }
 
     // Handles the PUSH r16 instruction
     #[inline]
-    fn push_r16(&mut self, com: &mut Components, reg: Reg16) {
+    fn push_r16<B: Bus>(&mut self, com: &mut B, reg: Reg16) {
         let val = reg.read(self);
         self.push_val(com, val);
     }
 
     // Handles the POP r16 instruction
     #[inline]
-    fn pop_r16(&mut self, com: &mut Components, reg: Reg16) {
-        let val = self.read16(com, self.sp);
+    fn pop_r16<B: Bus>(&mut self, com: &mut B, reg: Reg16) {
+        let low = self.read8_idu(com, self.sp);
+        let high = self.read8_idu(com, self.sp.wrapping_add(1));
+        let val = low as u16 | (high as u16) << 8;
         reg.write(self, val);
         self.sp = self.sp.wrapping_add(2);
     }
 
     // Handles the RET instruction and its CC varients
     #[inline]
-    fn ret(&mut self, com: &mut Components) {
+    fn ret<B: Bus>(&mut self, com: &mut B) {
         self.pop_r16(com, Reg16::PC);
         self.run_cycle(com);
     }
@@ -494,37 +578,37 @@ impl CPU {
 
     // Handles the SUB r8 instruction and its flags
     #[inline]
-    fn sub_r8(&mut self, mut target: Reg8) {
-        let val = target.read(self);
+    fn sub_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let val = target.read(self, com);
         self.a = self.alu_sub(val, false);
     }
 
     // Handles the SBC A, r8 instruction and its flags
     #[inline]
-    fn sbc_a_r8(&mut self, mut target: Reg8) {
-        let val = target.read(self);
+    fn sbc_a_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let val = target.read(self, com);
         self.a = self.alu_sub(val, self.get_c());
     }
 
     // Handles the CP r8 instruction and its flags
     #[inline]
-    fn cp_r8(&mut self, mut target: Reg8) {
-        let val = target.read(self);
+    fn cp_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let val = target.read(self, com);
         self.alu_sub(val, false);
     }
 
     // Handles the AND r8 instruction and its flags
     #[inline]
-    fn and_a_r8(&mut self, mut target: Reg8) {
-        let val = target.read(self);
+    fn and_a_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let val = target.read(self, com);
         self.a &= val;
         self.set_flags(self.a == 0, false, true, false);
     }
 
     // Handles the ADD A, r8 instruction and its flags
     #[inline]
-    fn add_a_r8(&mut self, mut target: Reg8) {
-        let val = target.read(self);
+    fn add_a_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
+        let val = target.read(self, com);
         let (res, carry) = self.a.overflowing_add(val);
         self.set_flags(
             res == 0,
@@ -537,9 +621,9 @@ impl CPU {
 
     // Handles the ADC A, r8 instruction and its flags
     #[inline]
-    fn adc_a_r8(&mut self, mut target: Reg8) {
+    fn adc_a_r8<B: Bus>(&mut self, com: &mut B, target: Reg8) {
         let carry = self.get_c() as u8;
-        let val = target.read(self);
+        let val = target.read(self, com);
         let res = self.a.wrapping_add(val).wrapping_add(carry);
         self.set_flags(
             res == 0,
@@ -551,7 +635,7 @@ impl CPU {
     }
 
     #[inline]
-    fn add_hl_r16(&mut self, com: &mut Components, reg: Reg16) {
+    fn add_hl_r16<B: Bus>(&mut self, com: &mut B, reg: Reg16) {
         self.run_cycle(com);
         let val = reg.read(self);
         let (res, carry) = Reg16::HL.read(self).overflowing_add(val);
@@ -561,3 +645,20 @@ impl CPU {
         Reg16::HL.write(self, res);
     }
 }
+
+impl_state!(CPU {
+    a,
+    b,
+    c,
+    d,
+    e,
+    f,
+    h,
+    l,
+    ime,
+    ime_queued,
+    halted,
+    sp,
+    pc,
+    opcode
+});
@@ -0,0 +1,130 @@
+use crate::{bus::Bus, symbols::SymbolTable, Gameboy};
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEM: [&str; 4] = ["(BC)", "(DE)", "(HL+)", "(HL-)"];
+const COND: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP ",
+];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// Disassembles the instruction starting with bytes[0], which is located at addr
+// Returns the text and the instruction's length
+// Absolute addresses (jump/call targets, a16 operands) are formatted with fmt_addr so they
+// can be replaced with labels
+pub fn disassemble(bytes: [u8; 3], addr: u16, fmt_addr: impl Fn(u16) -> String) -> (String, u16) {
+    let op = bytes[0];
+    let d8 = bytes[1];
+    let a16 = u16::from_le_bytes([bytes[1], bytes[2]]);
+    let r8 = bytes[1] as i8;
+    let jr_target = addr.wrapping_add(2).wrapping_add(r8 as u16);
+
+    // Opcodes are split into fields like xxyyyzzz, where yyy is also ppq
+    let x = op >> 6;
+    let y = (op >> 3 & 7) as usize;
+    let z = (op & 7) as usize;
+    let p = y >> 1;
+
+    let (text, len) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("NOP".into(), 1),
+            1 => (format!("LD ({}),SP", fmt_addr(a16)), 3),
+            2 => ("STOP".into(), 2),
+            3 => (format!("JR {}", fmt_addr(jr_target)), 2),
+            _ => (format!("JR {},{}", COND[y - 4], fmt_addr(jr_target)), 2),
+        },
+        (0, 1) if y & 1 == 0 => (format!("LD {},${a16:04X}", R16[p]), 3),
+        (0, 1) => (format!("ADD HL,{}", R16[p]), 1),
+        (0, 2) if y & 1 == 0 => (format!("LD {},A", R16_MEM[p]), 1),
+        (0, 2) => (format!("LD A,{}", R16_MEM[p]), 1),
+        (0, 3) if y & 1 == 0 => (format!("INC {}", R16[p]), 1),
+        (0, 3) => (format!("DEC {}", R16[p]), 1),
+        (0, 4) => (format!("INC {}", R8[y]), 1),
+        (0, 5) => (format!("DEC {}", R8[y]), 1),
+        (0, 6) => (format!("LD {},${d8:02X}", R8[y]), 2),
+        (0, 7) => {
+            let ops = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+            (ops[y].into(), 1)
+        }
+        (1, 6) if y == 6 => ("HALT".into(), 1),
+        (1, _) => (format!("LD {},{}", R8[y], R8[z]), 1),
+        (2, _) => (format!("{}{}", ALU[y], R8[z]), 1),
+        (3, 0) => match y {
+            0..=3 => (format!("RET {}", COND[y]), 1),
+            4 => (format!("LDH (${:04X}),A", 0xFF00 | d8 as u16), 2),
+            5 => (format!("ADD SP,{r8}"), 2),
+            6 => (format!("LDH A,(${:04X})", 0xFF00 | d8 as u16), 2),
+            _ => (format!("LD HL,SP{r8:+}"), 2),
+        },
+        (3, 1) if y & 1 == 0 => (format!("POP {}", R16_STACK[p]), 1),
+        (3, 1) => {
+            let ops = ["RET", "RETI", "JP HL", "LD SP,HL"];
+            (ops[p].into(), 1)
+        }
+        (3, 2) => match y {
+            0..=3 => (format!("JP {},{}", COND[y], fmt_addr(a16)), 3),
+            4 => ("LD ($FF00+C),A".into(), 1),
+            5 => (format!("LD ({}),A", fmt_addr(a16)), 3),
+            6 => ("LD A,($FF00+C)".into(), 1),
+            _ => (format!("LD A,({})", fmt_addr(a16)), 3),
+        },
+        (3, 3) => match y {
+            0 => (format!("JP {}", fmt_addr(a16)), 3),
+            1 => (disassemble_cb(d8), 2),
+            6 => ("DI".into(), 1),
+            7 => ("EI".into(), 1),
+            _ => (format!("DB ${op:02X}"), 1),
+        },
+        (3, 4) if y < 4 => (format!("CALL {},{}", COND[y], fmt_addr(a16)), 3),
+        (3, 5) if y & 1 == 0 => (format!("PUSH {}", R16_STACK[p]), 1),
+        (3, 5) if y == 1 => (format!("CALL {}", fmt_addr(a16)), 3),
+        (3, 6) => (format!("{}${d8:02X}", ALU[y]), 2),
+        (3, 7) => (format!("RST ${:02X}", y * 8), 1),
+        // The rest of the 0xC4-0xFD range is unused
+        _ => (format!("DB ${op:02X}"), 1),
+    };
+    (text, len)
+}
+
+fn disassemble_cb(op: u8) -> String {
+    let y = op >> 3 & 7;
+    let reg = R8[(op & 7) as usize];
+    match op >> 6 {
+        0 => format!("{} {reg}", ROT[y as usize]),
+        1 => format!("BIT {y},{reg}"),
+        2 => format!("RES {y},{reg}"),
+        _ => format!("SET {y},{reg}"),
+    }
+}
+
+// Disassembles the instruction at addr in the Gameboy's current memory map
+// Reads are passive, so this can be done at any time without disturbing anything
+pub fn disassemble_at(gb: &mut Gameboy, addr: u16, symbols: &SymbolTable) -> (String, u16) {
+    let com = &mut gb.components;
+    let bytes = [0, 1, 2].map(|i| com.read_passive(addr.wrapping_add(i)));
+    let com = &gb.components;
+    disassemble(bytes, addr, |target| {
+        match symbols.get(com.bank(target), target) {
+            Some(name) => name.to_string(),
+            None => format!("${target:04X}"),
+        }
+    })
+}
+
+// Formats the instruction about to be executed and the current registers as a line for trace logs
+pub fn trace_line(gb: &mut Gameboy, symbols: &SymbolTable) -> String {
+    let addr = gb.cpu.instruction_addr();
+    let bank = gb.components.bank(addr);
+    let (text, _) = disassemble_at(gb, addr, symbols);
+    let location = match symbols.resolve(bank, addr) {
+        Some(_) => format!("{bank:02X}:{addr:04X} {}", symbols.format(bank, addr)),
+        None => format!("{bank:02X}:{addr:04X}"),
+    };
+    let cpu = &gb.cpu;
+    format!(
+        "{location:32} {text:20} AF:{:02X}{:02X} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} CY:{}",
+        cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, gb.components.cycle
+    )
+}
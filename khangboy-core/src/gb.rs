@@ -1,6 +1,15 @@
 use crate::components::Components;
 use crate::cpu::CPU;
 use crate::rom::ROM;
+use crate::savestate::{self, State, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+
+// M-cycles per frame
+pub const FRAME_CYCLES: u64 = 70224 / 4;
+
+// Longest run_frame can take before giving up on the PPU finishing a frame
+// Turning the LCD back on starts a new frame, so a game that keeps doing that never finishes one
+// Real frames are always shorter than this, even the first one after the LCD is turned back on
+pub const MAX_FRAME_CYCLES: u64 = FRAME_CYCLES * 2;
 
 pub struct Gameboy {
     pub cpu: CPU,
@@ -24,4 +33,90 @@ impl Gameboy {
         }
         executed
     }
+
+    // Runs until the PPU finishes a frame, or for one frame's worth of time if the LCD is off
+    // This stops at the start of VBlank, so framebuffer is always a complete frame afterwards
+    // If no frame gets finished within MAX_FRAME_CYCLES, it stops there and frame stays the same
+    pub fn run_frame(&mut self) -> FrameInfo {
+        let start_frame = self.components.ppu.frame_count;
+        let mut cycles = 0;
+        while self.components.ppu.frame_count == start_frame && cycles < MAX_FRAME_CYCLES {
+            cycles += self.cpu.step(&mut self.components);
+        }
+        FrameInfo {
+            frame: self.components.ppu.frame_count,
+            cycles,
+            lcd_on: self.components.ppu.lcd_on(),
+        }
+    }
+
+    // Saves the entire state of the Gameboy
+    // It can only be loaded with the same ROM
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::default();
+        w.write(&STATE_MAGIC);
+        STATE_VERSION.save(&mut w);
+        self.components.rom_hash().save(&mut w);
+        self.cpu.save(&mut w);
+        self.components.save(&mut w);
+        w.buf
+    }
+
+    // Hashes everything that affects emulation, so two Gameboys that hash the same will keep
+    // doing the same thing given the same input
+    pub fn machine_state_hash(&self) -> u64 {
+        let mut w = StateWriter::default();
+        self.cpu.save_machine(&mut w);
+        self.components.save_machine(&mut w);
+        savestate::hash(&w.buf)
+    }
+
+    // Loads a state made by save_state
+    // Nothing changes if it can't be loaded
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+        if r.read(STATE_MAGIC.len()).ok() != Some(&STATE_MAGIC) {
+            return Err("Not a save state".into());
+        }
+        let mut version = 0u32;
+        version.load(&mut r)?;
+        if version != STATE_VERSION {
+            return Err(format!("Unsupported save state version {version}"));
+        }
+        let mut rom_hash = 0u64;
+        rom_hash.load(&mut r)?;
+        if rom_hash != self.components.rom_hash() {
+            return Err("Save state is for a different ROM".into());
+        }
+
+        // Something could still go wrong partway through, so keep a copy to go back to
+        let backup = self.save_state();
+        let result = self.load_body(&mut r);
+        if result.is_err() {
+            let mut r = StateReader::new(&backup);
+            r.read(STATE_MAGIC.len() + 4 + 8).unwrap();
+            self.load_body(&mut r).unwrap();
+        }
+        result
+    }
+
+    fn load_body(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.cpu.load(r)?;
+        self.components.load(r)?;
+        if !r.is_empty() {
+            return Err("Save state has extra data at the end".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    // Number of frames completed since power-on, including this one
+    pub frame: u64,
+    // M-cycles it took, which varies a bit since instructions can run past the end of a frame
+    pub cycles: u64,
+    // Whether the LCD was on at the end of the frame
+    // If it wasn't, framebuffer is blank
+    pub lcd_on: bool,
 }
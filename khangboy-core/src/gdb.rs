@@ -0,0 +1,717 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    bus::{Bus, BusObserver, ObservedBus},
+    components::Components,
+    disasm,
+    gb::MAX_FRAME_CYCLES,
+    symbols::SymbolTable,
+    Gameboy,
+};
+
+// GDB remote serial protocol stub
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+// GDB has no SM83 target, so registers are exposed as AF, BC, DE, HL, SP, PC (16-bit each),
+// which lines up with the start of GDB's Z80 register layout
+pub struct GdbStub {
+    stream: Option<TcpStream>,
+    buf: Vec<u8>,
+
+    state: StubState,
+    stop_reason: StopReason,
+
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+
+    symbols: SymbolTable,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StubState {
+    Stopped,
+    Running,
+    Stepping,
+    // GDB disconnected, so the Gameboy just runs normally
+    Detached,
+}
+
+#[derive(Clone, Copy)]
+enum StopReason {
+    Signal(u8),
+    Watchpoint(WatchKind, u16),
+}
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Breakpoint {
+    addr: u16,
+    // Breakpoints set on labels only trigger in the label's bank
+    // GDB's own breakpoints don't know about banks, so they trigger in any of them
+    bank: Option<u16>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Watchpoint {
+    kind: WatchKind,
+    addr: u16,
+    len: u16,
+}
+
+impl Watchpoint {
+    fn hit(&self, addr: u16, write: bool) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Write => write,
+            WatchKind::Read => !write,
+            WatchKind::Access => true,
+        };
+        kind_matches && addr.wrapping_sub(self.addr) < self.len
+    }
+}
+
+enum Packet {
+    // Sent as a raw 0x03 byte when the user hits Ctrl-C
+    Interrupt,
+    Command(Vec<u8>),
+}
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.khangboy.sm83">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>"#;
+
+// How long to wait for packets while stopped before handing control back to the caller
+const STOPPED_POLL_TIMEOUT: Duration = Duration::from_millis(10);
+
+impl GdbStub {
+    // Waits for GDB to connect
+    // The Gameboy starts out stopped so breakpoints can be set before anything runs
+    pub fn listen(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+
+    // Uses a connection to GDB that was already accepted
+    pub fn from_stream(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream: Some(stream),
+            buf: Vec::new(),
+            state: StubState::Stopped,
+            stop_reason: StopReason::Signal(SIGTRAP),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            symbols: SymbolTable::default(),
+        })
+    }
+
+    // Sets the symbols used by monitor commands
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    // Whether GDB is still connected
+    pub fn attached(&self) -> bool {
+        self.state != StubState::Detached
+    }
+
+    // Handles any packets from GDB, then runs for AT LEAST n cycles unless a breakpoint or
+    // watchpoint is hit, or the Gameboy is stopped
+    // Actual cycle count is returned
+    // Socket errors are treated like GDB detaching, so the Gameboy just keeps running
+    pub fn run(&mut self, gb: &mut Gameboy, cycles: u64) -> u64 {
+        if self.state == StubState::Detached {
+            return gb.run(cycles);
+        }
+        self.run_until(gb, |_, executed| executed >= cycles)
+    }
+
+    // Handles any packets from GDB, then runs until the PPU finishes a frame (see
+    // Gameboy::run_frame) unless a breakpoint or watchpoint is hit, or the Gameboy is stopped
+    // Actual cycle count is returned, and the frame is incomplete if execution stopped early
+    pub fn run_frame(&mut self, gb: &mut Gameboy) -> u64 {
+        if self.state == StubState::Detached {
+            return gb.run_frame().cycles;
+        }
+        let start_frame = gb.components.ppu.frame_count;
+        self.run_until(gb, |gb, executed| {
+            gb.components.ppu.frame_count != start_frame || executed >= MAX_FRAME_CYCLES
+        })
+    }
+
+    fn run_until(&mut self, gb: &mut Gameboy, done: impl Fn(&Gameboy, u64) -> bool) -> u64 {
+        self.poll(gb);
+
+        let mut executed = 0;
+        let mut first = true;
+        while !done(gb, executed) {
+            match self.state {
+                StubState::Stopped | StubState::Detached => break,
+                StubState::Running => {
+                    // Don't immediately stop again when continuing from a breakpoint
+                    if !first && self.at_breakpoint(gb) {
+                        self.stop(StopReason::Signal(SIGTRAP));
+                        break;
+                    }
+                    executed += self.step(gb);
+                }
+                StubState::Stepping => {
+                    executed += self.step(gb);
+                    if self.state == StubState::Stepping {
+                        self.stop(StopReason::Signal(SIGTRAP));
+                    }
+                }
+            }
+            first = false;
+        }
+
+        executed
+    }
+
+    fn at_breakpoint(&self, gb: &Gameboy) -> bool {
+        let addr = gb.cpu.instruction_addr();
+        self.breakpoints
+            .iter()
+            .any(|x| x.addr == addr && x.bank.is_none_or(|bank| bank == gb.components.bank(addr)))
+    }
+
+    // Steps by one instruction, stopping if a watchpoint gets hit
+    fn step(&mut self, gb: &mut Gameboy) -> u64 {
+        let mut watcher = Watcher {
+            watchpoints: &self.watchpoints,
+            hit: None,
+        };
+        let mut bus = ObservedBus {
+            com: &mut gb.components,
+            observer: &mut watcher,
+        };
+        let cycles = gb.cpu.step(&mut bus);
+        if let Some(hit) = watcher.hit {
+            self.stop(hit);
+        }
+        cycles
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.state = StubState::Stopped;
+        self.stop_reason = reason;
+        let reply = self.stop_reply();
+        self.send(reply.as_bytes());
+    }
+
+    fn stop_reply(&self) -> String {
+        match self.stop_reason {
+            StopReason::Signal(signal) => format!("S{signal:02x}"),
+            StopReason::Watchpoint(kind, addr) => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T{SIGTRAP:02x}{name}:{addr:04x};")
+            }
+        }
+    }
+
+    // Reads and handles every packet that's available
+    fn poll(&mut self, gb: &mut Gameboy) {
+        loop {
+            while let Some(packet) = self.next_packet() {
+                match packet {
+                    Packet::Interrupt => {
+                        if self.state != StubState::Stopped {
+                            self.stop(StopReason::Signal(SIGINT));
+                        }
+                    }
+                    Packet::Command(cmd) => {
+                        if let Some(reply) = self.handle_command(gb, &cmd) {
+                            self.send(&reply);
+                        }
+                    }
+                }
+                if self.state == StubState::Detached {
+                    return;
+                }
+            }
+
+            // Only block while stopped, and even then only briefly
+            let stopped = self.state == StubState::Stopped;
+            if !self.receive(stopped) || !stopped {
+                return;
+            }
+        }
+    }
+
+    // Reads whatever is available from the socket, returning false if nothing was read
+    // The connection is dropped if it's closed or broken
+    fn receive(&mut self, block: bool) -> bool {
+        let stream = match &mut self.stream {
+            Some(x) => x,
+            None => return false,
+        };
+
+        let mut temp = [0u8; 0x1000];
+        let result = stream
+            .set_nonblocking(!block)
+            .and_then(|_| stream.set_read_timeout(Some(STOPPED_POLL_TIMEOUT)))
+            .and_then(|_| stream.read(&mut temp));
+        match result {
+            Ok(0) => {
+                self.detach();
+                false
+            }
+            Ok(n) => {
+                self.buf.extend_from_slice(&temp[..n]);
+                true
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+            Err(_) => {
+                self.detach();
+                false
+            }
+        }
+    }
+
+    // Parses the next packet out of the receive buffer
+    fn next_packet(&mut self) -> Option<Packet> {
+        if self.buf.is_empty() {
+            self.receive(false);
+        }
+
+        loop {
+            match self.buf.first() {
+                None => return None,
+                Some(0x03) => {
+                    self.buf.remove(0);
+                    return Some(Packet::Interrupt);
+                }
+                Some(b'$') => break,
+                // Acks and anything else that isn't the start of a packet
+                Some(_) => {
+                    self.buf.remove(0);
+                }
+            }
+        }
+
+        // $<data>#<checksum>
+        let end = match self.buf.iter().position(|&x| x == b'#') {
+            Some(x) if x + 2 < self.buf.len() => x,
+            _ => return None,
+        };
+        let data = self.buf[1..end].to_vec();
+        let checksum = std::str::from_utf8(&self.buf[end + 1..end + 3])
+            .ok()
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        self.buf.drain(..end + 3);
+
+        if checksum != Some(data.iter().fold(0u8, |acc, &x| acc.wrapping_add(x))) {
+            self.write_raw(b"-");
+            return None;
+        }
+        self.write_raw(b"+");
+        Some(Packet::Command(data))
+    }
+
+    // Handles a command, returning the reply if there is one
+    fn handle_command(&mut self, gb: &mut Gameboy, cmd: &[u8]) -> Option<Vec<u8>> {
+        // Commands are always ASCII, so anything else is malformed
+        // This also means every byte index into it is a char boundary
+        let cmd = match std::str::from_utf8(cmd) {
+            Ok(x) if x.is_ascii() => x,
+            _ => return Some(b"E01".to_vec()),
+        };
+        let (kind, args) = cmd.split_at(1.min(cmd.len()));
+        let reply = match kind {
+            // Why the Gameboy last stopped
+            "?" => self.stop_reply(),
+            // Read all registers
+            "g" => (0..6).map(|x| hex_u16(read_reg(gb, x))).collect(),
+            // Write all registers
+            "G" => {
+                for i in 0..6 {
+                    match args.get(i * 4..i * 4 + 4).and_then(parse_reg) {
+                        Some(val) => write_reg(gb, i, val),
+                        None => return Some(b"E01".to_vec()),
+                    }
+                }
+                "OK".into()
+            }
+            // Read one register
+            "p" => match parse_hex(args) {
+                Some(x) if x < 6 => hex_u16(read_reg(gb, x as usize)),
+                _ => "E01".into(),
+            },
+            // Write one register
+            "P" => {
+                let parsed = args
+                    .split_once('=')
+                    .and_then(|(reg, val)| Some((parse_hex(reg)?, parse_reg(val)?)));
+                match parsed {
+                    Some((reg, val)) if reg < 6 => {
+                        write_reg(gb, reg as usize, val);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            // Read memory
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => (0..len)
+                    .map(|i| {
+                        let val = gb.components.read_passive(addr.wrapping_add(i));
+                        format!("{val:02x}")
+                    })
+                    .collect(),
+                None => "E01".into(),
+            },
+            // Write memory
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_addr_len(range)?, decode_hex(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (i, val) in data.into_iter().enumerate() {
+                            gb.components
+                                .write_passive(addr.wrapping_add(i as u16), val);
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            // Continue or step, optionally from a new address
+            "c" | "s" => {
+                if let Some(addr) = parse_hex_u16(args) {
+                    gb.cpu.set_instruction_addr(&mut gb.components, addr);
+                }
+                self.state = if kind == "c" {
+                    StubState::Running
+                } else {
+                    StubState::Stepping
+                };
+                // The reply gets sent once the Gameboy stops again
+                return None;
+            }
+            // Insert or remove a breakpoint or watchpoint
+            "Z" | "z" => match self.set_breakpoint(args, kind == "Z") {
+                Some(()) => "OK".into(),
+                None => String::new(),
+            },
+            // Kill
+            "k" => {
+                self.detach();
+                return None;
+            }
+            // Detach
+            "D" => {
+                self.send(b"OK");
+                self.detach();
+                return None;
+            }
+            // Set thread, but there's only one
+            "H" => "OK".into(),
+            "q" => match args.strip_prefix("Rcmd,") {
+                Some(cmd) => self.handle_monitor(gb, cmd),
+                None => self.handle_query(args),
+            },
+            // Anything else is unsupported, which is signaled with an empty reply
+            _ => String::new(),
+        };
+        Some(reply.into_bytes())
+    }
+
+    fn handle_query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=1000;qXfer:features:read+".into()
+        } else if query == "Attached" {
+            "1".into()
+        } else if query == "C" {
+            "QC1".into()
+        } else if query == "fThreadInfo" {
+            "m1".into()
+        } else if query == "sThreadInfo" {
+            "l".into()
+        } else if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            // Sent in chunks, prefixed with "m" if there's more to come or "l" if this is the end
+            match args.split_once(',').and_then(|(offset, len)| {
+                Some((parse_hex(offset)? as usize, parse_hex(len)? as usize))
+            }) {
+                Some((offset, len)) => {
+                    let start = offset.min(TARGET_XML.len());
+                    let end = (start + len).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{prefix}{}", &TARGET_XML[start..end])
+                }
+                None => "E01".into(),
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    // Handles Z/z packets, returning None if the type isn't supported
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> Option<()> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?;
+        let addr = parse_hex_u16(parts.next()?)?;
+        let len = parse_hex_u16(parts.next()?)?;
+        let watch_kind = match kind {
+            // Software and hardware breakpoints are the same thing here
+            "0" | "1" => {
+                self.set_code_breakpoint(Breakpoint { addr, bank: None }, insert);
+                return Some(());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+        let watchpoint = Watchpoint {
+            kind: watch_kind,
+            addr,
+            len: len.max(1),
+        };
+        self.watchpoints.retain(|&x| x != watchpoint);
+        if insert {
+            self.watchpoints.push(watchpoint);
+        }
+        Some(())
+    }
+
+    fn set_code_breakpoint(&mut self, breakpoint: Breakpoint, insert: bool) {
+        self.breakpoints.retain(|&x| x != breakpoint);
+        if insert {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    // Handles "monitor" commands, which are sent hex encoded
+    // Output is sent as O packets before the final reply
+    fn handle_monitor(&mut self, gb: &mut Gameboy, cmd: &str) -> String {
+        let cmd = match decode_hex(cmd) {
+            Some(x) => String::from_utf8_lossy(&x).into_owned(),
+            None => return "E01".into(),
+        };
+        let mut words = cmd.split_whitespace();
+        let output = match (words.next(), words.next(), words.next()) {
+            (Some(cmd @ ("break" | "delete")), Some(target), None) => {
+                match self.parse_location(target) {
+                    Some(breakpoint) => {
+                        self.set_code_breakpoint(breakpoint, cmd == "break");
+                        let bank = breakpoint.bank.unwrap_or(0);
+                        let name = self.symbols.format(bank, breakpoint.addr);
+                        format!("{cmd} at {bank:02X}:{:04X} ({name})\n", breakpoint.addr)
+                    }
+                    None => format!("Unknown label or address {target}\n"),
+                }
+            }
+            (Some("symbol"), Some(target), None) => match self.parse_location(target) {
+                Some(Breakpoint { addr, bank }) => {
+                    let bank = bank.unwrap_or_else(|| gb.components.bank(addr));
+                    format!(
+                        "{bank:02X}:{addr:04X} {}\n",
+                        self.symbols.format(bank, addr)
+                    )
+                }
+                None => format!("Unknown label or address {target}\n"),
+            },
+            (Some("disas"), target, count) => {
+                let addr = match target {
+                    Some(x) => self.parse_location(x).map(|x| x.addr),
+                    None => Some(gb.cpu.instruction_addr()),
+                };
+                let count = count.and_then(|x| x.parse().ok()).unwrap_or(10);
+                match addr {
+                    Some(addr) => self.disassemble(gb, addr, count),
+                    None => "Unknown label or address\n".into(),
+                }
+            }
+            _ => "Commands:\n\
+                  break <label or address>\n\
+                  delete <label or address>\n\
+                  symbol <label or address>\n\
+                  disas [label or address] [count]\n"
+                .into(),
+        };
+        self.send(format!("O{}", encode_hex(output.as_bytes())).as_bytes());
+        "OK".into()
+    }
+
+    // Parses either a label or a hex address, with the bank being known only for labels
+    fn parse_location(&self, s: &str) -> Option<Breakpoint> {
+        if let Some((bank, addr)) = self.symbols.find(s) {
+            return Some(Breakpoint {
+                addr,
+                bank: Some(bank),
+            });
+        }
+        let s = s.trim_start_matches("0x").trim_start_matches('$');
+        let addr = parse_hex_u16(s)?;
+        Some(Breakpoint { addr, bank: None })
+    }
+
+    fn disassemble(&self, gb: &mut Gameboy, mut addr: u16, count: usize) -> String {
+        let mut out = String::new();
+        for _ in 0..count {
+            let bank = gb.components.bank(addr);
+            if let Some(name) = self.symbols.get(bank, addr) {
+                out += &format!("{name}:\n");
+            }
+            let (text, len) = disasm::disassemble_at(gb, addr, &self.symbols);
+            out += &format!("  {bank:02X}:{addr:04X}  {text}\n");
+            addr = addr.wrapping_add(len);
+        }
+        out
+    }
+
+    fn detach(&mut self) {
+        self.state = StubState::Detached;
+        self.stream = None;
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    // Sends a packet, framed and checksummed
+    fn send(&mut self, data: &[u8]) {
+        let checksum = data.iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{checksum:02x}").as_bytes());
+        self.write_raw(&packet);
+    }
+
+    // Drops the connection if it's broken
+    fn write_raw(&mut self, data: &[u8]) {
+        if let Some(stream) = &mut self.stream {
+            if stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.write_all(data))
+                .is_err()
+            {
+                self.detach();
+            }
+        }
+    }
+}
+
+// Keeps track of watchpoint hits
+// Instruction fetches aren't data reads, so they don't trigger watchpoints
+struct Watcher<'a> {
+    watchpoints: &'a [Watchpoint],
+    hit: Option<StopReason>,
+}
+
+impl Watcher<'_> {
+    fn check(&mut self, addr: u16, write: bool) {
+        if self.hit.is_some() {
+            return;
+        }
+        if let Some(x) = self.watchpoints.iter().find(|x| x.hit(addr, write)) {
+            self.hit = Some(StopReason::Watchpoint(x.kind, addr));
+        }
+    }
+}
+
+impl BusObserver for Watcher<'_> {
+    fn on_read(&mut self, _com: &Components, addr: u16) {
+        self.check(addr, false);
+    }
+
+    fn on_write(&mut self, _com: &Components, addr: u16, _val: u8) {
+        self.check(addr, true);
+    }
+}
+
+// Register numbers match the order in TARGET_XML
+fn read_reg(gb: &Gameboy, reg: usize) -> u16 {
+    let cpu = &gb.cpu;
+    match reg {
+        0 => (cpu.a as u16) << 8 | cpu.f as u16,
+        1 => (cpu.b as u16) << 8 | cpu.c as u16,
+        2 => (cpu.d as u16) << 8 | cpu.e as u16,
+        3 => (cpu.h as u16) << 8 | cpu.l as u16,
+        4 => cpu.sp,
+        5 => cpu.instruction_addr(),
+        _ => unreachable!(),
+    }
+}
+
+fn write_reg(gb: &mut Gameboy, reg: usize, val: u16) {
+    let cpu = &mut gb.cpu;
+    let (hi, lo) = ((val >> 8) as u8, val as u8);
+    match reg {
+        0 => (cpu.a, cpu.f) = (hi, lo & 0xF0),
+        1 => (cpu.b, cpu.c) = (hi, lo),
+        2 => (cpu.d, cpu.e) = (hi, lo),
+        3 => (cpu.h, cpu.l) = (hi, lo),
+        4 => cpu.sp = val,
+        5 => cpu.set_instruction_addr(&mut gb.components, val),
+        _ => unreachable!(),
+    }
+}
+
+// Registers are sent in target byte order, which is little-endian
+fn hex_u16(val: u16) -> String {
+    format!("{:02x}{:02x}", val as u8, (val >> 8) as u8)
+}
+
+fn parse_reg(s: &str) -> Option<u16> {
+    match decode_hex(s)?[..] {
+        [lo, hi] => Some((hi as u16) << 8 | lo as u16),
+        _ => None,
+    }
+}
+
+// Unlike registers, addresses and lengths are sent big-endian
+fn parse_hex_u16(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+// Decodes pairs of hex digits, failing if there's an odd number of them or anything else
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digit = |x: u8| (x as char).to_digit(16).map(|x| x as u8);
+    let bytes = s.as_bytes();
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    bytes
+        .chunks_exact(2)
+        .map(|x| Some(digit(x[0])? << 4 | digit(x[1])?))
+        .collect()
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn parse_addr_len(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex_u16(addr)?, parse_hex_u16(len)?))
+}
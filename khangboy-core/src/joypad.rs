@@ -1,4 +1,4 @@
-use crate::util::BitIndex;
+use crate::{savestate::impl_state, util::BitIndex};
 
 pub struct Joypad {
     pub cur_input: u8,
@@ -43,3 +43,9 @@ impl Joypad {
         self.p1 = val & 0x30;
     }
 }
+
+impl_state!(Joypad {
+    cur_input,
+    p1,
+    last_p1
+});
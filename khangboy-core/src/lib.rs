@@ -1,11 +1,23 @@
 pub use gb::Gameboy;
 pub mod apu;
+pub mod bus;
+pub mod cdl;
 pub mod components;
 pub mod cpu;
+pub mod disasm;
 pub mod gb;
+pub mod gdb;
 pub mod joypad;
+pub mod movie;
+pub mod palette;
 pub mod ppu;
+pub mod profiler;
+pub mod rewind;
 pub mod rom;
+pub mod runahead;
+pub mod savestate;
 pub mod serial;
+pub mod symbols;
+pub mod test_rom;
 pub mod timer;
 pub mod util;
@@ -0,0 +1,179 @@
+use std::path::Path;
+
+use crate::{
+    gb::FrameInfo,
+    savestate::{State, StateReader, StateWriter},
+    Gameboy,
+};
+
+// How many frames there are between each hash of the machine state
+const HASH_INTERVAL: usize = 60;
+
+// Joypad input for every frame from power-on, which replays exactly since the emulator is
+// deterministic
+// The file format is a header followed by the fields in order:
+//   "KBMV" magic, u32 format version
+//   u64 ROM hash, initial cart RAM, one input byte per frame, state hash every 60 frames
+pub struct Movie {
+    rom_hash: u64,
+    initial_ram: Vec<u8>,
+    pub inputs: Vec<u8>,
+    // Hash of the state after every HASH_INTERVAL frames, for catching desyncs
+    hashes: Vec<u64>,
+}
+
+const MOVIE_MAGIC: [u8; 4] = *b"KBMV";
+const MOVIE_VERSION: u32 = 3;
+
+impl Movie {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|x| x.to_string())?;
+        let mut r = StateReader::new(&data);
+        if r.read(MOVIE_MAGIC.len()).ok() != Some(&MOVIE_MAGIC) {
+            return Err("Not a movie".into());
+        }
+        let mut version = 0u32;
+        version.load(&mut r)?;
+        if version != MOVIE_VERSION {
+            return Err(format!("Unsupported movie version {version}"));
+        }
+
+        let mut movie = Movie {
+            rom_hash: 0,
+            initial_ram: Vec::new(),
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+        };
+        movie.rom_hash.load(&mut r)?;
+        movie.initial_ram.load(&mut r)?;
+        movie.inputs.load(&mut r)?;
+        let mut count = 0usize;
+        count.load(&mut r)?;
+        for _ in 0..count {
+            let mut hash = 0u64;
+            hash.load(&mut r)?;
+            movie.hashes.push(hash);
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut w = StateWriter::default();
+        w.write(&MOVIE_MAGIC);
+        MOVIE_VERSION.save(&mut w);
+        self.rom_hash.save(&mut w);
+        self.initial_ram.save(&mut w);
+        self.inputs.save(&mut w);
+        self.hashes.len().save(&mut w);
+        for x in &self.hashes {
+            x.save(&mut w);
+        }
+        std::fs::write(path, w.buf)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.inputs.len()
+    }
+}
+
+// Records input into a movie
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    // Starts recording, which has to be done right after powering on
+    pub fn new(gb: &Gameboy) -> Result<Self, String> {
+        if gb.components.cycle != 0 {
+            return Err("Movies have to start from power-on".into());
+        }
+        Ok(Self {
+            movie: Movie {
+                rom_hash: gb.components.rom_hash(),
+                initial_ram: gb.components.cart_ram().to_vec(),
+                inputs: Vec::new(),
+                hashes: Vec::new(),
+            },
+        })
+    }
+
+    // Runs one frame with the given input
+    pub fn run_frame(&mut self, gb: &mut Gameboy, input: u8) -> FrameInfo {
+        let info = run_frame(gb, input);
+        self.movie.inputs.push(input);
+        if self.movie.inputs.len().is_multiple_of(HASH_INTERVAL) {
+            self.movie.hashes.push(gb.machine_state_hash());
+        }
+        info
+    }
+
+    pub fn frames(&self) -> usize {
+        self.movie.inputs.len()
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Plays a movie back
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    Playing,
+    Finished,
+    // The state didn't match what was recorded after this many frames
+    Desynced(usize),
+}
+
+impl MoviePlayer {
+    // Starts playing, which has to be done right after powering on with the same ROM
+    pub fn new(movie: Movie, gb: &mut Gameboy) -> Result<Self, String> {
+        if gb.components.cycle != 0 {
+            return Err("Movies have to start from power-on".into());
+        }
+        if movie.rom_hash != gb.components.rom_hash() {
+            return Err("Movie is for a different ROM".into());
+        }
+        let ram = gb.components.cart_ram_mut();
+        if movie.initial_ram.len() != ram.len() {
+            return Err("Movie's cart RAM doesn't match the ROM".into());
+        }
+        ram.copy_from_slice(&movie.initial_ram);
+        Ok(Self { movie, frame: 0 })
+    }
+
+    // Runs the next frame using the recorded input
+    pub fn run_frame(&mut self, gb: &mut Gameboy) -> PlaybackStatus {
+        let Some(&input) = self.movie.inputs.get(self.frame) else {
+            return PlaybackStatus::Finished;
+        };
+        run_frame(gb, input);
+        self.frame += 1;
+
+        if self.frame.is_multiple_of(HASH_INTERVAL) {
+            let expected = self.movie.hashes.get(self.frame / HASH_INTERVAL - 1);
+            if expected.is_some_and(|&x| x != gb.machine_state_hash()) {
+                return PlaybackStatus::Desynced(self.frame);
+            }
+        }
+        PlaybackStatus::Playing
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+fn run_frame(gb: &mut Gameboy, input: u8) -> FrameInfo {
+    gb.components.joypad.cur_input = input;
+    gb.run_frame()
+}
@@ -0,0 +1,107 @@
+// Turns the shades that the PPU outputs into colors for displaying
+// The DMG only has 4 shades (0 is the lightest and 3 is the darkest), so what they actually look
+// like is up to the frontend
+
+// Which palette register a pixel was drawn with, as stored in PPU::framebuffer_palettes
+pub const BGP: u8 = 0;
+pub const OBP0: u8 = 1;
+pub const OBP1: u8 = 2;
+
+// Makes an opaque RGBA color from 0xRRGGBB
+const fn rgb(x: u32) -> [u8; 4] {
+    [(x >> 16) as u8, (x >> 8) as u8, x as u8, 0xFF]
+}
+
+// RGBA colors for shades 0 to 3
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::GRAY
+    }
+}
+
+impl Palette {
+    pub const GRAY: Self = Self::from_rgb([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]);
+    // The green tint of the original DMG screen
+    pub const DMG: Self = Self::from_rgb([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]);
+    // The Game Boy Pocket's screen, which is closer to black and white
+    pub const POCKET: Self = Self::from_rgb([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]);
+    // The Game Boy Light's backlit screen
+    pub const LIGHT: Self = Self::from_rgb([0x00B581, 0x009A71, 0x00694A, 0x004F3B]);
+
+    // Every built-in palette along with a name for it
+    pub const PRESETS: [(&'static str, Self); 4] = [
+        ("Gray", Self::GRAY),
+        ("DMG", Self::DMG),
+        ("Pocket", Self::POCKET),
+        ("Light", Self::LIGHT),
+    ];
+
+    // Makes a palette from 0xRRGGBB colors for shades 0 to 3
+    pub const fn from_rgb(colors: [u32; 4]) -> Self {
+        Self {
+            colors: [
+                rgb(colors[0]),
+                rgb(colors[1]),
+                rgb(colors[2]),
+                rgb(colors[3]),
+            ],
+        }
+    }
+
+    pub fn color(&self, shade: u8) -> [u8; 4] {
+        self.colors[shade as usize & 3]
+    }
+
+    // Converts shades to RGBA, where rgba has to be 4 times as long as shades
+    pub fn shades_to_rgba(&self, shades: &[u8], rgba: &mut [u8]) {
+        for (&shade, out) in shades.iter().zip(rgba.chunks_exact_mut(4)) {
+            out.copy_from_slice(&self.color(shade));
+        }
+    }
+}
+
+// A palette for each of the palette registers
+// They're all the same normally, but the CGB boot ROM colors DMG games this way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Palettes {
+    pub bg: Palette,
+    pub obp0: Palette,
+    pub obp1: Palette,
+}
+
+impl Palettes {
+    // Uses the same palette for everything
+    pub fn uniform(palette: Palette) -> Self {
+        Self {
+            bg: palette,
+            obp0: palette,
+            obp1: palette,
+        }
+    }
+
+    // Gets the palette for BGP, OBP0, or OBP1
+    pub fn get(&self, source: u8) -> &Palette {
+        match source {
+            OBP0 => &self.obp0,
+            OBP1 => &self.obp1,
+            _ => &self.bg,
+        }
+    }
+
+    // Converts a framebuffer to RGBA, using sources (see PPU::framebuffer_palettes) to pick the
+    // palette for each pixel
+    pub fn framebuffer_to_rgba(&self, shades: &[u8], sources: &[u8], rgba: &mut [u8]) {
+        for ((&shade, &source), out) in shades
+            .iter()
+            .zip(sources.iter())
+            .zip(rgba.chunks_exact_mut(4))
+        {
+            out.copy_from_slice(&self.get(source).color(shade));
+        }
+    }
+}
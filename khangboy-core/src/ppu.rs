@@ -1,6 +1,13 @@
 use std::ops::{Index, IndexMut};
 
-use crate::util::BitIndex;
+use crate::{
+    palette,
+    savestate::{impl_state, State, StateReader, StateWriter},
+    util::BitIndex,
+};
+
+// Longest mode 3 can be, with a fine scroll of 7, the window, and 10 objects in different tiles
+const MAX_MODE3_DOTS: u16 = 172 + 7 + 6 + 10 * 11;
 
 // The pixel processing unit, which handles display stuff
 #[allow(clippy::upper_case_acronyms)]
@@ -26,17 +33,55 @@ pub struct PPU {
     fetcher: PixelFetcher,
 
     scanline_objs: [OAMObject; 10],
+    // OAM slot that each object in scanline_objs came from, for debug_layers
+    // Not saved in save states since it doesn't affect emulation
+    scanline_objs_slot: [u8; 10],
     scanline_objs_count: usize,
 
     window_triggered: bool,
     window_lcd_y: u8,
+    // Set when the window started on the last pixel of a line (WX=166), which makes it cover the
+    // entire next line
+    window_wrap: bool,
 
     pub oam_dma_running: bool,
+    // Last value written to the DMA register
     pub oam_dma_src: u8,
+    // Source page of the transfer that's running, which can differ from oam_dma_src while a
+    // restarted transfer is still starting up
+    pub oam_dma_page: u8,
     pub oam_dma_idx: u8,
+    // M-cycles until a newly requested transfer starts, where 0 means none is pending
+    pub oam_dma_start_delay: u8,
+    // Last byte copied by the transfer, which is what the CPU sees on a conflicting bus
+    pub oam_dma_byte: u8,
 
     temp_framebuffer: [u8; 160 * 144],
     pub framebuffer: [u8; 160 * 144],
+    // Which palette register each pixel in framebuffer used, see palette::BGP
+    temp_framebuffer_palettes: [u8; 160 * 144],
+    pub framebuffer_palettes: [u8; 160 * 144],
+
+    // Not saved in save states since it's a frontend setting
+    pub debug_layers: DebugLayers,
+    // Number of lines where mode 3 went past MAX_MODE3_DOTS and had to be cut short
+    // Not saved in save states either since it's only there to notice when that happens
+    pub mode3_overruns: u32,
+
+    // Number of frames completed since power-on
+    // While the LCD is off, a frame is counted every 70224 dots so time still advances in frames
+    pub frame_count: u64,
+    lcd_off_dots: u32,
+
+    // The first line after the LCD is turned on skips OAM scan and reports mode 0 instead
+    first_line: bool,
+    // The first frame after the LCD is turned on isn't shown, so the screen stays blank
+    blank_frame: bool,
+
+    // Whether any STAT interrupt source was active on the last M-cycle
+    stat_line: bool,
+    // Set when writing STAT triggers the interrupt, which gets requested on the next tick
+    stat_write_interrupt: bool,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -47,6 +92,24 @@ enum DrawMode {
     Drawing = 3,
 }
 
+// Layers that can be hidden for debugging rendering
+// These only change what gets drawn, so timing, registers, and save states are the same either way
+// A hidden object still covers objects with lower priority, since that's decided when fetching
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugLayers {
+    pub hide_bg: bool,
+    pub hide_window: bool,
+    pub hide_objs: bool,
+    // Bit n hides the object in OAM slot n
+    pub hidden_objs: u64,
+}
+
+impl DebugLayers {
+    pub fn hides_obj(&self, index: usize) -> bool {
+        self.hide_objs || self.hidden_objs & (1 << index) != 0
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 #[repr(C)]
 struct OAMObject {
@@ -68,25 +131,40 @@ impl Default for PPU {
             window_y: 0,
             window_x: 0,
             lcd_control: 0,
-            lcd_status: 0x84,
+            lcd_status: 0,
             lcd_y: 0,
             lcd_y_compare: 0,
             lcd_x: 0,
             bg_palette: 0xFC,
             obp0: 0xFF,
             obp1: 0xFF,
-            draw_mode: DrawMode::OAMScan,
+            draw_mode: DrawMode::HBlank,
             scanline_dot: 0,
             fetcher: Default::default(),
             scanline_objs: Default::default(),
+            scanline_objs_slot: [0; 10],
             scanline_objs_count: 0,
             window_triggered: false,
             window_lcd_y: 0,
+            window_wrap: false,
             oam_dma_running: false,
             oam_dma_src: 0x00,
+            oam_dma_page: 0x00,
             oam_dma_idx: 0,
+            oam_dma_start_delay: 0,
+            oam_dma_byte: 0xFF,
             temp_framebuffer: [0u8; 160 * 144],
             framebuffer: [0u8; 160 * 144],
+            temp_framebuffer_palettes: [palette::BGP; 160 * 144],
+            framebuffer_palettes: [palette::BGP; 160 * 144],
+            debug_layers: Default::default(),
+            mode3_overruns: 0,
+            frame_count: 0,
+            lcd_off_dots: 0,
+            first_line: false,
+            blank_frame: false,
+            stat_line: false,
+            stat_write_interrupt: false,
         }
     }
 }
@@ -94,15 +172,27 @@ impl Default for PPU {
 impl PPU {
     // Ticks one M-cycle
     pub fn tick(&mut self) -> (bool, bool) {
+        self.tick_dots(4)
+    }
+
+    // Ticks some number of dots, so writes from the CPU can land partway through an M-cycle
+    // Returns whether the VBlank and STAT interrupts were requested
+    pub fn tick_dots(&mut self, dots: u8) -> (bool, bool) {
         let mut vblank_interrupt = false;
-        let mut stat_interrupt = false;
+        let mut stat_interrupt = std::mem::take(&mut self.stat_write_interrupt);
 
         // Check if the PPU and LCD are enabled
-        // TODO: This should render a blank screen
+        // Everything is reset when the LCD gets turned off, see write_lcdc
         if !self.lcd_control.test(7) {
+            self.lcd_off_dots += dots as u32;
+            if self.lcd_off_dots >= 70224 {
+                self.lcd_off_dots -= 70224;
+                self.frame_count += 1;
+            }
             return (vblank_interrupt, stat_interrupt);
         }
-        for _ in 0..4 {
+        self.lcd_off_dots = 0;
+        for _ in 0..dots {
             // This is hell
             // http://pixelbits.16-b.it/GBEDG/ppu/#the-pixel-fifo
             match self.draw_mode {
@@ -110,27 +200,41 @@ impl PPU {
                 // 80 dots, 1 sprite checked per 2 dots
                 // TODO: This is just a guess and probably isn't T-cycle accurate
                 DrawMode::OAMScan => {
-                    if self.scanline_dot % 2 == 0 {
+                    if self.first_line {
+                        // Nothing is scanned, so no objects are drawn on this line
+                    } else if self.scanline_dot.is_multiple_of(2) {
                         if self.scanline_dot == 0 {
                             self.scanline_objs_count = 0;
                             if self.lcd_y == self.window_y {
                                 self.window_triggered = true;
                             }
                         }
-                        let obj = self.oam[self.scanline_dot as usize / 2];
+                        // Only Y is checked, so objects that are off-screen horizontally still count
+                        // towards the limit of 10 per line
+                        let index = self.scanline_dot as usize / 2;
+                        let obj = self.oam[index];
                         let height = if self.lcd_control.test(2) { 16 } else { 8 };
-                        if obj.x != 0
-                            && self.lcd_y.wrapping_add(16) >= obj.y
+                        if self.lcd_y.wrapping_add(16) >= obj.y
                             && self.lcd_y.wrapping_add(16) < obj.y.wrapping_add(height)
                             && self.scanline_objs_count != self.scanline_objs.len()
                         {
-                            self.scanline_objs[self.scanline_objs_count] = obj;
+                            // Objects get fetched from left to right, so they're kept sorted by X
+                            // Objects at the same X stay in OAM order
+                            let count = self.scanline_objs_count;
+                            let pos = self.scanline_objs[..count].partition_point(|x| x.x <= obj.x);
+                            self.scanline_objs.copy_within(pos..count, pos + 1);
+                            self.scanline_objs_slot.copy_within(pos..count, pos + 1);
+                            self.scanline_objs[pos] = obj;
+                            self.scanline_objs_slot[pos] = index as u8;
                             self.scanline_objs_count += 1;
                         }
                     }
                     if self.scanline_dot == 79 {
-                        self.scanline_objs[..self.scanline_objs_count].sort_by_key(|x| x.x);
+                        self.first_line = false;
+                        // The first tile fetched is thrown away, which takes 6 dots
                         self.fetcher = Default::default();
+                        self.fetcher.stall = 6;
+                        self.fetcher.penalty_tile = i16::MIN;
                         self.lcd_x = -(self.viewport_x as i16 & 7);
                         self.draw_mode = DrawMode::Drawing;
                     }
@@ -140,37 +244,49 @@ impl PPU {
                     self.scanline_dot += 1;
                     if self.tick_fetcher() && self.fetcher.bg_fifo.count != 0 {
                         if self.lcd_x >= 0 {
+                            // On DMG, disabling the background blanks it as pixels are drawn rather
+                            // than when they're fetched
+                            // Layers hidden by debug_layers are drawn the same way
+                            // The FIFO gets cleared when the window starts, so every pixel in it is
+                            // from the same layer
+                            let hidden = if self.fetcher.bg_window {
+                                self.debug_layers.hide_window
+                            } else {
+                                self.debug_layers.hide_bg
+                            };
                             let bg = self.fetcher.bg_fifo.pop();
-                            let bg_col = if (bg & 4) != 0 {
-                                (self.bg_palette >> ((bg & 3) * 2)) & 3
+                            let (bg, bg_col) = if self.lcd_control.test(0) && !hidden {
+                                (bg, (self.bg_palette >> ((bg & 3) * 2)) & 3)
                             } else {
-                                0x00
+                                (0x00, 0x00)
                             };
-                            let col = if self.fetcher.sprite_fifo.count != 0 {
+                            let (col, source) = if self.fetcher.sprite_fifo.count != 0 {
+                                let slot = self.fetcher.sprite_fifo.tag(0);
                                 let sprite = self.fetcher.sprite_fifo.pop();
-                                if sprite & 3 != 0 {
-                                    if sprite.test(4) && (bg & 3 != 0) {
-                                        bg_col
+                                if sprite & 3 != 0
+                                    && self.lcd_control.test(1)
+                                    && !self.debug_layers.hides_obj(slot as usize)
+                                {
+                                    // Objects behind the background only show over color 0
+                                    if sprite.test(4) && bg & 3 != 0 {
+                                        (bg_col, palette::BGP)
+                                    } else if sprite.test(3) {
+                                        ((self.obp1 >> ((sprite & 3) * 2)) & 3, palette::OBP1)
                                     } else {
-                                        if sprite.test(3) {
-                                            (self.obp1 >> ((sprite & 3) * 2)) & 3
-                                        } else {
-                                            (self.obp0 >> ((sprite & 3) * 2)) & 3
-                                        }
+                                        ((self.obp0 >> ((sprite & 3) * 2)) & 3, palette::OBP0)
                                     }
                                 } else {
-                                    bg_col
+                                    (bg_col, palette::BGP)
                                 }
                             } else {
-                                bg_col
+                                (bg_col, palette::BGP)
                             };
-                            self.temp_framebuffer
-                                [self.lcd_y as usize * 160 + self.lcd_x as usize] = col;
+                            let idx = self.lcd_y as usize * 160 + self.lcd_x as usize;
+                            self.temp_framebuffer[idx] = col;
+                            self.temp_framebuffer_palettes[idx] = source;
                             self.lcd_x += 1;
                             if self.lcd_x == 160 {
-                                if self.lcd_control.test(3) {
-                                    stat_interrupt = true;
-                                }
+                                self.window_wrap = self.fetcher.bg_window && self.window_x == 166;
                                 self.draw_mode = DrawMode::HBlank;
                             }
                         } else {
@@ -178,31 +294,38 @@ impl PPU {
                             self.lcd_x += 1;
                         }
                     }
-                    assert!(self.scanline_dot <= 389);
+                    if self.scanline_dot >= 80 + MAX_MODE3_DOTS
+                        && self.draw_mode == DrawMode::Drawing
+                    {
+                        // The window restarts every time it's turned back on during the line, so
+                        // toggling it enough can keep the line from finishing
+                        // End it here instead of drawing into the next line
+                        self.mode3_overruns = self.mode3_overruns.saturating_add(1);
+                        self.draw_mode = DrawMode::HBlank;
+                    }
                 }
                 DrawMode::HBlank => {
                     self.scanline_dot += 1;
                     if self.scanline_dot == 456 {
                         self.lcd_y += 1;
-                        if self.fetcher.bg_window {
+                        // The window's line counter only advances on lines it was drawn on
+                        if self.fetcher.window_drawn {
                             self.window_lcd_y += 1;
                         }
                         self.scanline_dot = 0;
-                        if self.lcd_y == self.lcd_y_compare && self.lcd_control.test(6) {
-                            stat_interrupt = true;
-                        }
                         self.draw_mode = if self.lcd_y == 144 {
                             vblank_interrupt = true;
-                            if self.lcd_control.test(4) {
-                                stat_interrupt = true;
-                            }
                             self.window_triggered = false;
-                            self.framebuffer.clone_from(&self.temp_framebuffer);
+                            self.window_wrap = false;
+                            if !self.blank_frame {
+                                self.framebuffer.clone_from(&self.temp_framebuffer);
+                                self.framebuffer_palettes
+                                    .clone_from(&self.temp_framebuffer_palettes);
+                            }
+                            self.blank_frame = false;
+                            self.frame_count += 1;
                             DrawMode::VBlank
                         } else {
-                            if self.lcd_control.test(5) {
-                                stat_interrupt = true;
-                            }
                             DrawMode::OAMScan
                         }
                     }
@@ -211,175 +334,228 @@ impl PPU {
                     self.scanline_dot += 1;
                     if self.scanline_dot == 456 {
                         self.lcd_y += 1;
-                        if self.lcd_y == self.lcd_y_compare && self.lcd_control.test(6) {
-                            stat_interrupt = true;
-                        }
                         self.scanline_dot = 0;
                     }
                     if self.lcd_y > 153 {
                         self.lcd_y = 0;
                         self.window_lcd_y = 0;
-                        if self.lcd_control.test(5) {
-                            stat_interrupt = true;
-                        }
                         self.draw_mode = DrawMode::OAMScan;
                     }
                 }
             }
         }
 
+        // The interrupt is only requested when the STAT line goes from low to high, so it won't
+        // fire again if another source becomes active while one already is
+        let stat_line = self.stat_line();
+        if stat_line && !self.stat_line {
+            stat_interrupt = true;
+        }
+        self.stat_line = stat_line;
+
         (vblank_interrupt, stat_interrupt)
     }
 
+    pub fn lcd_on(&self) -> bool {
+        self.lcd_control.test(7)
+    }
+
+    // Value of LY that's compared against LYC, which is None while it's being updated
+    // At the start of each line, the comparison takes an extra M-cycle to see the new LY
+    // Line 153 only shows up as LY for its first M-cycle, then LY goes back to 0 early
+    fn ly_compare(&self) -> Option<u8> {
+        match (self.lcd_y, self.scanline_dot) {
+            (0, _) => Some(0),
+            (_, 0..4) => None,
+            (153, 4..8) => Some(153),
+            (153, _) => Some(0),
+            (ly, _) => Some(ly),
+        }
+    }
+
+    fn lyc_match(&self) -> bool {
+        self.ly_compare() == Some(self.lcd_y_compare)
+    }
+
+    // OR of every enabled STAT interrupt source
+    fn stat_line(&self) -> bool {
+        let mode = self.mode();
+        (self.lcd_status.test(3) && mode == DrawMode::HBlank)
+            || (self.lcd_status.test(4) && mode == DrawMode::VBlank)
+            // The OAM scan source also triggers at the start of line 144 like any other line
+            || (self.lcd_status.test(5)
+                && (mode == DrawMode::OAMScan || (self.lcd_y == 144 && self.scanline_dot < 4)))
+            || (self.lcd_status.test(6) && self.lyc_match())
+    }
+
+    // The mode as seen by the CPU
+    fn mode(&self) -> DrawMode {
+        if self.first_line && self.draw_mode == DrawMode::OAMScan {
+            DrawMode::HBlank
+        } else {
+            self.draw_mode
+        }
+    }
+
     pub fn tick_fetcher(&mut self) -> bool {
+        if self.fetcher.stall != 0 {
+            self.fetcher.stall -= 1;
+            return false;
+        }
+
         if self.lcd_control.test(1)
-            && !self.fetcher.fetching_sprite
             && self.fetcher.sprite_next_idx != self.scanline_objs_count
-            && self.scanline_objs[self.fetcher.sprite_next_idx].x as u16 <= self.lcd_x as u16 + 8
+            && self.scanline_objs[self.fetcher.sprite_next_idx].x as i16 <= self.lcd_x + 8
         {
-            self.fetcher.fetching_sprite = true;
-            self.fetcher.sprite_ticks = 0;
-            self.fetcher.sprite_state = FetcherState::GetTile;
-            self.fetcher.sprite_obj = self.scanline_objs[self.fetcher.sprite_next_idx];
+            let obj = self.scanline_objs[self.fetcher.sprite_next_idx];
+            self.fetch_sprite(obj, self.scanline_objs_slot[self.fetcher.sprite_next_idx]);
             self.fetcher.sprite_next_idx += 1;
+            // This dot is part of the penalty
+            self.fetcher.stall = self.sprite_penalty(obj) - 1;
+            return false;
         }
 
-        if self.fetcher.fetching_sprite {
-            self.tick_fetcher_sprite();
-            return false;
+        if self.fetcher.bg_window && !self.lcd_control.test(5) {
+            // Turning the window off partway through a line goes back to the background, picking
+            // up at the tile after the pixels that have already been fetched
+            self.fetcher.bg_window = false;
+            self.fetcher.x =
+                ((self.lcd_x + self.fetcher.bg_fifo.count as i16 + (self.viewport_x & 7) as i16)
+                    / 8) as u8;
         }
 
         if self.window_triggered
             && self.lcd_control.test(5)
             && !self.fetcher.bg_window
-            && self.lcd_x >= self.window_x as i16 - 7
+            && (self.lcd_x >= self.window_x as i16 - 7 || self.window_wrap)
         {
+            // Restarting the fetcher for the window pauses drawing for 6 dots
+            // That happens even if nothing has been fetched yet, which only takes extra time here
+            if self.fetcher.x == 0 && self.fetcher.bg_ticks == 0 {
+                self.fetcher.stall = 6;
+            }
+            // With WX below 7, the window starts left of the screen and gets cut off
+            if self.lcd_x <= 0 && self.window_x < 7 {
+                self.lcd_x = self.window_x as i16 - 7;
+            }
             self.fetcher.bg_window = true;
+            self.fetcher.window_drawn = true;
             self.fetcher.bg_fifo = Default::default();
             self.fetcher.x = 0;
             self.fetcher.bg_state = FetcherState::GetTile;
+            self.fetcher.bg_ticks = 0;
+            self.fetcher.penalty_tile = i16::MIN;
         }
         self.tick_fetcher_bg();
 
         true
     }
 
-    fn tick_fetcher_sprite(&mut self) {
-        // Each stage takes 2 M-cycles
-        self.fetcher.sprite_ticks += 1;
-        if self.fetcher.sprite_ticks % 2 == 1 {
-            return;
+    // How many dots fetching an object pauses drawing for
+    // The fetch itself takes 6 dots, but if it's the first object in a background tile, it also
+    // has to wait for the background fetcher to finish fetching that tile
+    // https://gbdev.io/pandocs/Rendering.html#mode-3-length
+    fn sprite_penalty(&mut self, obj: OAMObject) -> u16 {
+        if obj.x == 0 {
+            return 11;
         }
 
-        self.fetcher.sprite_state = match self.fetcher.sprite_state {
-            FetcherState::GetTile => {
-                self.fetcher.sprite_tile = self.fetcher.sprite_obj.tile;
-                FetcherState::GetTileDataLow
+        // Position of the object's leftmost pixel relative to the tiles being fetched
+        let pos = if self.fetcher.bg_window {
+            obj.x as i16 + 7 - self.window_x as i16
+        } else {
+            obj.x as i16 + (self.viewport_x & 7) as i16
+        };
+        let tile = pos >> 3;
+        if tile == self.fetcher.penalty_tile {
+            return 6;
+        }
+        self.fetcher.penalty_tile = tile;
+        6 + (5 - (pos & 7)).max(0) as u16
+    }
+
+    // Fetches an object's pixels on the current line and mixes them into the object FIFO
+    fn fetch_sprite(&mut self, obj: OAMObject, slot: u8) {
+        // 8x16 objects ignore bit 0 of the tile index, so the top half is always the even tile
+        // TODO: Investigate mid-scanline OBJ size change behavior
+        let (tile, height) = if self.lcd_control.test(2) {
+            (obj.tile & 0xFE, 16)
+        } else {
+            (obj.tile, 8)
+        };
+        let mut row = self.lcd_y.wrapping_add(16).wrapping_sub(obj.y) & (height - 1);
+        if obj.flags.test(6) {
+            // Flipping vertically also swaps the two tiles of an 8x16 object
+            row = height - 1 - row;
+        }
+        let addr = tile as usize * 16 + row as usize * 2;
+        let low = self.vram[addr & 0x1FFF];
+        let high = self.vram[(addr + 1) & 0x1FFF];
+
+        // The FIFO starts at the next pixel to be drawn, and pixels left of the screen are cut off
+        let start = obj.x as i16 - 8 - self.lcd_x.max(0);
+        for i in 0..8 {
+            let idx = start + i as i16;
+            if idx < 0 {
+                continue;
             }
-            FetcherState::GetTileDataLow | FetcherState::GetTileDataHigh => {
-                let tile_addr = self.fetcher.sprite_tile as usize * 16;
-
-                // TODO: Investigate mid-scanline OBJ size change behavior
-                let offset = if self.fetcher.sprite_obj.flags.test(6) {
-                    if self.lcd_status.test(2) {
-                        30 - (self.lcd_y - (self.fetcher.sprite_obj.y - 16)) as usize * 2
-                    } else {
-                        14 - (self.lcd_y - (self.fetcher.sprite_obj.y - 16)) as usize * 2
-                    }
-                } else {
-                    (self.lcd_y - (self.fetcher.sprite_obj.y - 16)) as usize * 2
-                };
-                if self.fetcher.sprite_state == FetcherState::GetTileDataLow {
-                    self.fetcher.sprite_low = self.vram[(tile_addr + offset) & 0x1FFF];
-                    FetcherState::GetTileDataHigh
-                } else {
-                    self.fetcher.sprite_high = self.vram[(tile_addr + offset + 1) & 0x1FFF];
-                    FetcherState::Push
-                }
+            let idx = idx as u8;
+
+            let bit = if obj.flags.test(5) { i } else { 7 - i };
+
+            let pixel = 0u8
+                .set(0, low.test(bit))
+                .set(1, high.test(bit))
+                .set(3, obj.flags.test(4))
+                .set(4, obj.flags.test(7));
+            // Objects fetched earlier have priority, so only transparent pixels are replaced
+            while self.fetcher.sprite_fifo.count < idx {
+                self.fetcher.sprite_fifo.push(0);
             }
-            FetcherState::Push => {
-                for i in 0..8 {
-                    if self.fetcher.sprite_obj.x + i < 8 {
-                        continue;
-                    }
-
-                    let bit = if self.fetcher.sprite_obj.flags.test(5) {
-                        i
-                    } else {
-                        7 - i
-                    };
-
-                    let pixel = 0u8
-                        .set(0, self.fetcher.sprite_low.test(bit))
-                        .set(1, self.fetcher.sprite_high.test(bit))
-                        .set(3, self.fetcher.sprite_obj.flags.test(4))
-                        .set(4, self.fetcher.sprite_obj.flags.test(1));
-                    if i >= self.fetcher.sprite_fifo.count {
-                        self.fetcher.sprite_fifo.push(pixel);
-                    } else if self.fetcher.sprite_fifo[i as usize] & 3 == 0 {
-                        self.fetcher.sprite_fifo[i as usize] = pixel;
-                    }
-                }
-                self.fetcher.fetching_sprite = false;
-                FetcherState::GetTile
+            if idx == self.fetcher.sprite_fifo.count {
+                self.fetcher.sprite_fifo.push(pixel);
+            } else if self.fetcher.sprite_fifo[idx as usize] & 3 == 0 {
+                self.fetcher.sprite_fifo[idx as usize] = pixel;
+            } else {
+                continue;
             }
+            self.fetcher.sprite_fifo.set_tag(idx as usize, slot);
         }
     }
 
     fn tick_fetcher_bg(&mut self) {
-        // Each stage takes 2 M-cycles
-        self.fetcher.bg_ticks += 1;
-        if self.fetcher.bg_ticks % 2 == 1 {
-            return;
+        // Each stage takes 2 dots, except for pushing, which is retried every dot until the FIFO
+        // is empty
+        // The registers that pick the address are read on the first dot, then VRAM on the second,
+        // so a write can land between the two
+        if self.fetcher.bg_state != FetcherState::Push {
+            self.fetcher.bg_ticks += 1;
+            if self.fetcher.bg_ticks % 2 == 1 {
+                self.fetcher.bg_addr = self.bg_fetch_addr();
+                return;
+            }
         }
 
+        let addr = self.fetcher.bg_addr as usize & 0x1FFF;
         self.fetcher.bg_state = match self.fetcher.bg_state {
             FetcherState::GetTile => {
-                let map_bit = if self.fetcher.bg_window { 6 } else { 3 };
-                let map_addr = if self.lcd_control.test(map_bit) {
-                    0x1C00
-                } else {
-                    0x1800
-                };
-                let (x, y) = if self.fetcher.bg_window {
-                    (self.fetcher.x, self.window_lcd_y / 8)
-                } else {
-                    (
-                        (self.fetcher.x.wrapping_add(self.viewport_x / 8)) & 0x1F,
-                        (self.lcd_y.wrapping_add(self.viewport_y)) / 8,
-                    )
-                };
-                self.fetcher.bg_tile =
-                    self.vram[map_addr + ((y as usize * 32 + x as usize) & 0x3FF)];
+                self.fetcher.bg_tile = self.vram[addr];
                 FetcherState::GetTileDataLow
             }
-            FetcherState::GetTileDataLow | FetcherState::GetTileDataHigh => {
-                let tile_addr = if self.lcd_control.test(4) {
-                    self.fetcher.bg_tile as usize * 16
-                } else {
-                    (0x1000 + self.fetcher.bg_tile as i8 as isize * 16) as usize
-                };
-
-                let offset = if self.fetcher.bg_window {
-                    (self.window_lcd_y & 7) as usize * 2
-                } else {
-                    ((self.lcd_y + self.viewport_y) & 7) as usize * 2
-                };
-                if self.fetcher.bg_state == FetcherState::GetTileDataLow {
-                    self.fetcher.bg_low = self.vram[(tile_addr + offset) & 0x1FFF];
-                    FetcherState::GetTileDataHigh
-                } else {
-                    self.fetcher.bg_high = self.vram[(tile_addr + offset + 1) & 0x1FFF];
-                    FetcherState::Push
-                }
+            FetcherState::GetTileDataLow => {
+                self.fetcher.bg_low = self.vram[addr];
+                FetcherState::GetTileDataHigh
+            }
+            FetcherState::GetTileDataHigh => {
+                self.fetcher.bg_high = self.vram[addr];
+                FetcherState::Push
             }
             FetcherState::Push => {
                 if self.fetcher.bg_fifo.count == 0 {
                     for i in (0..=7).rev() {
                         self.fetcher.bg_fifo.push(
                             0u8.set(0, self.fetcher.bg_low.test(i))
-                                .set(1, self.fetcher.bg_high.test(i))
-                                .set(2, self.lcd_control.test(0)),
+                                .set(1, self.fetcher.bg_high.test(i)),
                         );
                     }
                     self.fetcher.x += 1;
@@ -391,6 +567,42 @@ impl PPU {
         }
     }
 
+    // Gets the VRAM address that the background fetcher's current stage reads from
+    // SCX and the tile map bit only matter for getting the tile, but SCY is used by every stage
+    fn bg_fetch_addr(&self) -> u16 {
+        let y = if self.fetcher.bg_window {
+            self.window_lcd_y
+        } else {
+            self.lcd_y.wrapping_add(self.viewport_y)
+        };
+        match self.fetcher.bg_state {
+            FetcherState::GetTile => {
+                let map_bit = if self.fetcher.bg_window { 6 } else { 3 };
+                let map_addr = if self.lcd_control.test(map_bit) {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                let x = if self.fetcher.bg_window {
+                    self.fetcher.x
+                } else {
+                    self.fetcher.x.wrapping_add(self.viewport_x / 8) & 0x1F
+                };
+                map_addr + ((y as u16 / 8 * 32 + x as u16) & 0x3FF)
+            }
+            FetcherState::GetTileDataLow | FetcherState::GetTileDataHigh => {
+                let tile_addr = if self.lcd_control.test(4) {
+                    self.fetcher.bg_tile as u16 * 16
+                } else {
+                    (0x1000 + self.fetcher.bg_tile as i8 as i16 * 16) as u16
+                };
+                let high = (self.fetcher.bg_state == FetcherState::GetTileDataHigh) as u16;
+                tile_addr + (y & 7) as u16 * 2 + high
+            }
+            FetcherState::Push => 0,
+        }
+    }
+
     pub fn read_vram(&self, addr: u16) -> u8 {
         if self.draw_mode < DrawMode::Drawing || !self.lcd_control.test(7) {
             self.vram[addr as usize & 0x1FFF]
@@ -406,9 +618,8 @@ impl PPU {
     }
 
     pub fn read_oam(&self, addr: u16) -> u8 {
-        // TODO: Reads from 0xFEA0-0xFEFF can trigger OAM corruption on DMG
         let index = addr as usize & 0xFF;
-        if index < 0xA0 && (self.draw_mode < DrawMode::OAMScan || !self.lcd_control.test(7)) {
+        if index < 0xA0 && (self.mode() < DrawMode::OAMScan || !self.lcd_control.test(7)) {
             // SAFETY: index is bounds checked
             unsafe {
                 let oam_bytes = self.oam.as_ptr() as *const u8;
@@ -419,10 +630,98 @@ impl PPU {
         }
     }
 
+    // Gets the row of OAM that the CPU would corrupt right now, if any
+    // OAM is split into 20 rows of 8 bytes, and the PPU reads one every M-cycle during mode 2
+    // The first row never gets corrupted since it has no row before it
+    fn oam_bug_row(&self) -> Option<usize> {
+        let row = self.scanline_dot as usize / 4;
+        (self.mode() == DrawMode::OAMScan && row != 0).then_some(row)
+    }
+
+    // Gets one of the 4 16-bit words in an OAM row
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let obj = &self.oam[row * 2 + word / 2];
+        if word.is_multiple_of(2) {
+            u16::from_le_bytes([obj.y, obj.x])
+        } else {
+            u16::from_le_bytes([obj.tile, obj.flags])
+        }
+    }
+
+    fn set_oam_word(&mut self, row: usize, word: usize, val: u16) {
+        let obj = &mut self.oam[row * 2 + word / 2];
+        let [low, high] = val.to_le_bytes();
+        if word.is_multiple_of(2) {
+            (obj.y, obj.x) = (low, high);
+        } else {
+            (obj.tile, obj.flags) = (low, high);
+        }
+    }
+
+    // Mixes the first word of a row with the row before it, then copies the rest of that row over
+    fn corrupt_oam_row(&mut self, row: usize, mix: impl Fn(u16, u16, u16) -> u16) {
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        self.set_oam_word(row, 0, mix(a, b, c));
+        for word in 1..4 {
+            self.set_oam_word(row, word, self.oam_word(row - 1, word));
+        }
+    }
+
+    // DMG corrupts OAM when the CPU writes to it, or has an address in it on the bus, during mode 2
+    // Which row gets corrupted only depends on timing, not the address
+    // See https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+    pub fn oam_bug_write(&mut self) {
+        if let Some(row) = self.oam_bug_row() {
+            self.corrupt_oam_row(row, |a, b, c| ((a ^ c) & (b ^ c)) ^ c);
+        }
+    }
+
+    // Reads corrupt OAM the same way as writes, but with a different pattern
+    pub fn oam_bug_read(&mut self) {
+        if let Some(row) = self.oam_bug_row() {
+            self.corrupt_oam_row(row, |a, b, c| b | (a & c));
+        }
+    }
+
+    // A read while the address register is being incremented or decremented also smears the row
+    // before the current one over 2 rows, except near the start and end of OAM
+    pub fn oam_bug_read_idu(&mut self) {
+        let Some(row) = self.oam_bug_row() else {
+            return;
+        };
+        if (4..19).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 1, 2);
+            self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            for word in 0..4 {
+                let val = self.oam_word(row - 1, word);
+                self.set_oam_word(row, word, val);
+                self.set_oam_word(row - 2, word, val);
+            }
+        }
+        self.oam_bug_read();
+    }
+
+    // Writes a byte into OAM from a DMA transfer, which isn't blocked by the PPU mode
+    pub fn write_oam_dma(&mut self, index: u8, val: u8) {
+        let index = index as usize;
+        if index < 0xA0 {
+            // SAFETY: index is bounds checked
+            unsafe {
+                let oam_bytes = self.oam.as_mut_ptr() as *mut u8;
+                *oam_bytes.add(index) = val;
+            }
+        }
+    }
+
     pub fn write_oam(&mut self, addr: u16, val: u8) {
         // TODO: What happens with writes to 0xFEA0-0xFEFF?
         let index = addr as usize & 0xFF;
-        if index < 0xA0 && (self.draw_mode < DrawMode::OAMScan || !self.lcd_control.test(7)) {
+        if index < 0xA0 && (self.mode() < DrawMode::OAMScan || !self.lcd_control.test(7)) {
             // SAFETY: index is bounds checked
             unsafe {
                 let oam_bytes = self.oam.as_mut_ptr() as *mut u8;
@@ -452,23 +751,64 @@ impl PPU {
     }
 
     pub fn write_lcdc(&mut self, val: u8) {
+        let was_on = self.lcd_control.test(7);
         self.lcd_control = val;
+        if was_on && !val.test(7) {
+            // LY and the mode go back to 0, and the screen goes blank instead of keeping the last
+            // frame
+            self.lcd_y = 0;
+            self.window_lcd_y = 0;
+            self.window_triggered = false;
+            self.window_wrap = false;
+            self.scanline_dot = 0;
+            self.draw_mode = DrawMode::HBlank;
+            self.stat_line = false;
+            self.framebuffer.fill(0);
+            self.temp_framebuffer.fill(0);
+            self.framebuffer_palettes.fill(palette::BGP);
+            self.temp_framebuffer_palettes.fill(palette::BGP);
+        } else if !was_on && val.test(7) {
+            // The first line is 4 dots shorter and goes straight from mode 0 to mode 3
+            self.first_line = true;
+            self.blank_frame = true;
+            self.scanline_dot = 4;
+            self.scanline_objs_count = 0;
+            self.window_triggered = self.lcd_y == self.window_y;
+            self.draw_mode = DrawMode::OAMScan;
+        }
     }
 
     pub fn read_stat(&self) -> u8 {
         // Bit 7:   Always 1
         // Bit 2:   LYC=LY flag
         // Bit 1-0: Mode
-        self.lcd_status | ((self.lcd_y == self.lcd_y_compare) as u8) << 2 | (self.draw_mode as u8)
+        0x80 | self.lcd_status | (self.lyc_match() as u8) << 2 | (self.mode() as u8)
     }
 
     pub fn write_stat(&mut self, val: u8) {
+        // On DMG, every source is briefly enabled while STAT is being written, so this can
+        // trigger the interrupt during HBlank, VBlank, or when LY=LYC
+        // https://gbdev.io/pandocs/STAT.html#spurious-stat-interrupts
+        if self.lcd_control.test(7) {
+            let mode = self.mode();
+            let line = mode == DrawMode::HBlank || mode == DrawMode::VBlank || self.lyc_match();
+            if line && !self.stat_line {
+                self.stat_write_interrupt = true;
+            }
+            self.stat_line |= line;
+        }
+
         // Only bits 3-6 are writable
         self.lcd_status = val & 0b0111_1000;
     }
 
     pub fn read_ly(&self) -> u8 {
-        self.lcd_y
+        // Line 153 only reads as 153 for its first M-cycle
+        if self.lcd_y == 153 && self.scanline_dot >= 4 {
+            0
+        } else {
+            self.lcd_y
+        }
     }
 
     pub fn read_lyc(&self) -> u8 {
@@ -508,9 +848,10 @@ impl PPU {
     }
 
     pub fn write_dma(&mut self, val: u8) {
-        self.oam_dma_running = true;
-        self.oam_dma_idx = 0;
+        // The transfer starts after a delay, and a transfer that's already running keeps going
+        // until then
         self.oam_dma_src = val;
+        self.oam_dma_start_delay = 2;
     }
 
     pub fn read_wy(&self) -> u8 {
@@ -528,15 +869,34 @@ impl PPU {
     pub fn write_wx(&mut self, val: u8) {
         self.window_x = val;
     }
+
+    // Makes sure indices loaded from a state are in range
+    fn check_state(&self) -> Result<(), String> {
+        if self.scanline_objs_count > self.scanline_objs.len()
+            || self.fetcher.sprite_next_idx > self.scanline_objs_count
+        {
+            return Err("Invalid PPU object count".into());
+        }
+        if self.lcd_y > 153 || !(-7..=160).contains(&self.lcd_x) {
+            return Err("Invalid LCD position".into());
+        }
+        if self.oam_dma_idx > 160 {
+            return Err("Invalid OAM DMA position".into());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Default)]
 struct PixelFIFO {
     // Bit 0-1: Color index
-    // Bit 2: Enabled (BG only)
     // Bit 3: Palette (Sprites only)
     // Bit 4: Priority (Sprites only)
     inner: [u8; 16],
+    // Extra info about each pixel that's only used for debug_layers (the OAM slot of the object
+    // that sprite pixels came from)
+    // Not saved in save states since it doesn't affect emulation
+    tags: [u8; 16],
 
     count: u8,
     read_head: u8,
@@ -558,6 +918,17 @@ impl IndexMut<usize> for PixelFIFO {
 }
 
 impl PixelFIFO {
+    fn check_state(&self) -> Result<(), String> {
+        let len = self.inner.len() as u8;
+        if self.count > len
+            || self.read_head >= len
+            || self.write_head != (self.read_head + self.count) % len
+        {
+            return Err("Invalid pixel FIFO".into());
+        }
+        Ok(())
+    }
+
     pub fn push(&mut self, pixel: u8) {
         assert!(self.count < self.inner.len() as u8);
         self.inner[self.write_head as usize] = pixel;
@@ -565,6 +936,14 @@ impl PixelFIFO {
         self.count += 1;
     }
 
+    pub fn tag(&self, index: usize) -> u8 {
+        self.tags[(self.read_head as usize + index) % 16]
+    }
+
+    pub fn set_tag(&mut self, index: usize, tag: u8) {
+        self.tags[(self.read_head as usize + index) % 16] = tag;
+    }
+
     pub fn pop(&mut self) -> u8 {
         assert!(self.count != 0);
         let ret = self.inner[self.read_head as usize];
@@ -585,23 +964,134 @@ enum FetcherState {
 
 #[derive(Default)]
 struct PixelFetcher {
-    sprite_state: FetcherState,
-    sprite_ticks: usize,
     sprite_fifo: PixelFIFO,
     sprite_next_idx: usize,
-    sprite_obj: OAMObject,
-    sprite_tile: u8,
-    sprite_low: u8,
-    sprite_high: u8,
 
     bg_state: FetcherState,
     bg_ticks: usize,
     bg_fifo: PixelFIFO,
     bg_tile: u8,
+    // VRAM address picked on the first dot of the current stage
+    bg_addr: u16,
     bg_low: u8,
     bg_high: u8,
     bg_window: bool,
+    // Whether the window has been drawn at all on this line
+    window_drawn: bool,
 
     x: u8,
-    fetching_sprite: bool,
+
+    // Dots left before drawing continues, for the penalties that make mode 3 longer
+    stall: u16,
+    // Background tile that the last object penalty waited on
+    penalty_tile: i16,
+}
+
+impl_state!(PPU {
+    vram,
+    oam,
+    viewport_y,
+    viewport_x,
+    window_y,
+    window_x,
+    lcd_control,
+    lcd_status,
+    lcd_y,
+    lcd_y_compare,
+    lcd_x,
+    bg_palette,
+    obp0,
+    obp1,
+    draw_mode,
+    scanline_dot,
+    fetcher,
+    scanline_objs,
+    scanline_objs_count,
+    window_triggered,
+    window_lcd_y,
+    window_wrap,
+    oam_dma_running,
+    oam_dma_src,
+    oam_dma_page,
+    oam_dma_idx,
+    oam_dma_start_delay,
+    oam_dma_byte,
+    #[output]
+    temp_framebuffer,
+    #[output]
+    framebuffer,
+    #[output]
+    temp_framebuffer_palettes,
+    #[output]
+    framebuffer_palettes,
+    frame_count,
+    lcd_off_dots,
+    first_line,
+    blank_frame,
+    stat_line,
+    stat_write_interrupt
+}; PPU::check_state);
+
+impl_state!(OAMObject { y, x, tile, flags });
+
+impl_state!(PixelFIFO {
+    inner,
+    count,
+    read_head,
+    write_head
+}; PixelFIFO::check_state);
+
+impl_state!(PixelFetcher {
+    sprite_fifo,
+    sprite_next_idx,
+    bg_state,
+    bg_ticks,
+    bg_fifo,
+    bg_tile,
+    bg_addr,
+    bg_low,
+    bg_high,
+    bg_window,
+    window_drawn,
+    x,
+    stall,
+    penalty_tile
+});
+
+impl State for DrawMode {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut val = 0u8;
+        val.load(r)?;
+        *self = match val {
+            0 => DrawMode::HBlank,
+            1 => DrawMode::VBlank,
+            2 => DrawMode::OAMScan,
+            3 => DrawMode::Drawing,
+            _ => return Err("Invalid PPU mode".into()),
+        };
+        Ok(())
+    }
+}
+
+impl State for FetcherState {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut val = 0u8;
+        val.load(r)?;
+        *self = match val {
+            0 => FetcherState::GetTile,
+            1 => FetcherState::GetTileDataLow,
+            2 => FetcherState::GetTileDataHigh,
+            3 => FetcherState::Push,
+            _ => return Err("Invalid pixel fetcher state".into()),
+        };
+        Ok(())
+    }
 }
@@ -0,0 +1,225 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    bus::{BusObserver, CallEvent, ObservedBus},
+    components::Components,
+    symbols::SymbolTable,
+    Gameboy,
+};
+
+// Keeps a shadow call stack from CALL/RST/interrupt entries and RET/RETI exits, and counts how
+// many M-cycles are spent in each routine
+// The Gameboy has to be stepped through this for anything to be recorded
+#[derive(Default)]
+pub struct Profiler {
+    // Call tree, where every unique call stack gets a node
+    // Node 0 is the root, which is code that isn't inside of any tracked call
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    routines: HashMap<Routine, RoutineStats>,
+    last_cycle: Option<u64>,
+}
+
+// A routine is identified by its entry point
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Routine {
+    pub bank: u16,
+    pub addr: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoutineStats {
+    pub calls: u64,
+    // Cycles spent in the routine and everything it called
+    pub inclusive: u64,
+    // Cycles spent in the routine itself
+    pub exclusive: u64,
+}
+
+// An entry in the shadow call stack
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub routine: Routine,
+    // Address of the CALL/RST, or the instruction that got interrupted
+    pub from: u16,
+    pub interrupt: bool,
+    // Where the return address is on the stack
+    sp: u16,
+    start_cycle: u64,
+    node: usize,
+}
+
+struct Node {
+    routine: Option<Routine>,
+    parent: usize,
+    children: HashMap<Routine, usize>,
+    exclusive: u64,
+}
+
+// Games don't always return from what they call (e.g. popping the return address and jumping),
+// so this keeps the stack from growing forever
+const MAX_DEPTH: usize = 256;
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Steps the CPU by one instruction while recording calls and returns
+    pub fn step(&mut self, gb: &mut Gameboy) -> u64 {
+        let mut bus = ObservedBus {
+            com: &mut gb.components,
+            observer: self,
+        };
+        let cycles = gb.cpu.step(&mut bus);
+        self.account(gb.components.cycle);
+        cycles
+    }
+
+    // Runs for AT LEAST n cycles
+    // Actual cycle count is returned
+    pub fn run(&mut self, gb: &mut Gameboy, cycles: u64) -> u64 {
+        let mut executed = 0;
+        while executed < cycles {
+            executed += self.step(gb);
+        }
+        executed
+    }
+
+    // Forgets everything that's been recorded
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // The current call stack, from the outermost call to the innermost
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.stack
+    }
+
+    // Stats for every routine that's been called, sorted by exclusive cycles
+    pub fn routines(&self) -> Vec<(Routine, RoutineStats)> {
+        let mut routines: Vec<_> = self.routines.iter().map(|(&x, &y)| (x, y)).collect();
+        routines.sort_by(|x, y| y.1.exclusive.cmp(&x.1.exclusive).then(x.0.cmp(&y.0)));
+        routines
+    }
+
+    // Writes exclusive cycles per call stack in the folded format used by flamegraph.pl,
+    // inferno, and speedscope, e.g. "root;Main;UpdateSprites 1234"
+    pub fn write_folded(&self, out: &mut impl Write, symbols: &SymbolTable) -> std::io::Result<()> {
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.exclusive == 0 {
+                continue;
+            }
+
+            let mut names = Vec::new();
+            let mut cur = idx;
+            loop {
+                let node = &self.nodes[cur];
+                match node.routine {
+                    Some(x) => names.push(symbols.format(x.bank, x.addr)),
+                    None => {
+                        names.push("root".to_string());
+                        break;
+                    }
+                }
+                cur = node.parent;
+            }
+            names.reverse();
+            writeln!(out, "{} {}", names.join(";"), node.exclusive)?;
+        }
+        Ok(())
+    }
+
+    // Gives the cycles since the last call/return to whatever is on top of the stack
+    fn account(&mut self, cycle: u64) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                routine: None,
+                parent: 0,
+                children: HashMap::new(),
+                exclusive: 0,
+            });
+        }
+
+        // Going back in time (e.g. loading a state) leaves the call stack not matching the CPU's
+        // anymore, so it starts over from there
+        let last_cycle = self.last_cycle.unwrap_or(cycle);
+        if cycle < last_cycle {
+            self.stack.clear();
+        }
+        let elapsed = cycle.saturating_sub(last_cycle);
+        self.last_cycle = Some(cycle);
+        let node = self.stack.last().map_or(0, |x| x.node);
+        self.nodes[node].exclusive += elapsed;
+        if let Some(routine) = self.nodes[node].routine {
+            self.routines.entry(routine).or_default().exclusive += elapsed;
+        }
+    }
+
+    fn enter(&mut self, routine: Routine, from: u16, sp: u16, interrupt: bool, cycle: u64) {
+        self.account(cycle);
+        if self.stack.len() >= MAX_DEPTH {
+            return;
+        }
+
+        let parent = self.stack.last().map_or(0, |x| x.node);
+        let node = match self.nodes[parent].children.get(&routine) {
+            Some(&x) => x,
+            None => {
+                let idx = self.nodes.len();
+                self.nodes.push(Node {
+                    routine: Some(routine),
+                    parent,
+                    children: HashMap::new(),
+                    exclusive: 0,
+                });
+                self.nodes[parent].children.insert(routine, idx);
+                idx
+            }
+        };
+        self.routines.entry(routine).or_default().calls += 1;
+        self.stack.push(Frame {
+            routine,
+            from,
+            interrupt,
+            sp,
+            start_cycle: cycle,
+            node,
+        });
+    }
+
+    // Pops every frame whose return address is at or below where this one came from
+    // This way, frames that never returned get cleaned up once something further up does
+    fn leave(&mut self, sp: u16, cycle: u64) {
+        self.account(cycle);
+        while let Some(frame) = self.stack.last().copied() {
+            if frame.sp > sp {
+                break;
+            }
+            self.stack.pop();
+
+            // Recursive calls would count the same cycles more than once otherwise
+            if !self.stack.iter().any(|x| x.routine == frame.routine) {
+                self.routines.entry(frame.routine).or_default().inclusive +=
+                    cycle - frame.start_cycle;
+            }
+        }
+    }
+}
+
+// Calls and returns are all the profiler needs to see
+impl BusObserver for Profiler {
+    fn on_call_event(&mut self, com: &Components, event: CallEvent) {
+        match event {
+            CallEvent::Call { from, to, sp } | CallEvent::Interrupt { from, to, sp } => {
+                let routine = Routine {
+                    bank: com.bank(to),
+                    addr: to,
+                };
+                let interrupt = matches!(event, CallEvent::Interrupt { .. });
+                self.enter(routine, from, sp, interrupt, com.cycle);
+            }
+            CallEvent::Return { sp } => self.leave(sp, com.cycle),
+        }
+    }
+}
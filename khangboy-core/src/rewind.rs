@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use crate::{gb::FRAME_CYCLES, Gameboy};
+
+// Keeps a ring buffer of recent save states so the Gameboy can be stepped backwards
+// Only the newest state is stored in full
+// Every older one is stored as the difference from the state after it (XORed, then with runs of
+// zeroes compressed), which is small since most memory doesn't change between snapshots
+pub struct Rewind {
+    interval: u64,
+    capacity: usize,
+    next_snapshot: u64,
+
+    newest: Option<Vec<u8>>,
+    // Oldest first, so deltas.back() turns newest into the state before it
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    // Snapshots every interval_frames frames, keeping up to capacity of them
+    pub fn new(interval_frames: u64, capacity: usize) -> Self {
+        Self {
+            interval: interval_frames.max(1) * FRAME_CYCLES,
+            capacity: capacity.max(1),
+            next_snapshot: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Takes a snapshot if enough time has passed since the last one
+    // This should be called regularly while running normally
+    pub fn record(&mut self, gb: &Gameboy) {
+        if gb.components.cycle < self.next_snapshot {
+            return;
+        }
+        self.next_snapshot = gb.components.cycle + self.interval;
+
+        let state = gb.save_state();
+        if let Some(prev) = self.newest.replace(state) {
+            let delta = encode_delta(self.newest.as_ref().unwrap(), &prev);
+            self.deltas.push_back(delta);
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+    }
+
+    // Goes back to the previous snapshot, returning false if there aren't any left
+    pub fn step_back(&mut self, gb: &mut Gameboy) -> bool {
+        let (Some(newest), Some(delta)) = (&mut self.newest, self.deltas.pop_back()) else {
+            return false;
+        };
+        // Deltas only come from record, but nothing older is reachable if one is broken anyway
+        match apply_delta(newest, &delta) {
+            Ok(state) => *newest = state,
+            Err(_) => {
+                self.clear();
+                return false;
+            }
+        }
+
+        // The state was made from this Gameboy, so this can't really fail
+        let loaded = gb.load_state(newest).is_ok();
+        self.next_snapshot = gb.components.cycle + self.interval;
+        loaded
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.next_snapshot = 0;
+    }
+
+    // Number of snapshots that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Bytes used by every snapshot
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, |x| x.len())
+            + self.deltas.iter().map(|x| x.len()).sum::<usize>()
+    }
+}
+
+// Encodes the difference between two states, which can be applied to from to get to
+// The format is the length of to, followed by pairs of runs:
+//   (number of unchanged bytes, number of changed bytes, changed bytes XORed)
+// States can differ in length, so anything past the end of the shorter one is treated as zeroes
+pub fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor = |i: usize| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0);
+
+    let mut out = Vec::new();
+    write_varint(&mut out, to.len());
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        write_varint(&mut out, i - start);
+
+        let start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut out, i - start);
+        out.extend((start..i).map(xor));
+    }
+    out
+}
+
+// Applies a delta made by encode_delta to the state it was made from
+// The runs have to cover both states exactly, so a truncated delta is an error
+pub fn apply_delta(from: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let mut pos = 0;
+    let to_len = read_varint(delta, &mut pos)?;
+    let mut out = from.to_vec();
+    out.resize(from.len().max(to_len), 0);
+
+    let mut i = 0usize;
+    while pos < delta.len() {
+        let unchanged = read_varint(delta, &mut pos)?;
+        let changed = read_varint(delta, &mut pos)?;
+        let start = i.saturating_add(unchanged);
+        let end = start.saturating_add(changed);
+        if end > out.len() || changed > delta.len() - pos {
+            return Err("Delta doesn't match the state".into());
+        }
+        for (x, y) in out[start..end].iter_mut().zip(&delta[pos..]) {
+            *x ^= y;
+        }
+        pos += changed;
+        i = end;
+    }
+    if i != out.len() {
+        return Err("Delta is truncated".into());
+    }
+    out.truncate(to_len);
+    Ok(out)
+}
+
+// LEB128
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push(val as u8 | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let Some(&byte) = data.get(*pos) else {
+            return Err("Delta is truncated".into());
+        };
+        if shift >= usize::BITS {
+            return Err("Delta has a number that's too big".into());
+        }
+        *pos += 1;
+        val |= (byte as usize & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(val);
+        }
+        shift += 7;
+    }
+}
@@ -1,4 +1,7 @@
-pub trait ROM {
+use crate::savestate::{State, StateReader, StateWriter};
+
+// Mapper state (bank registers, cart RAM) is saved through State
+pub trait ROM: State {
     // 0x0000 to 0x7FFF
     fn read_rom(&mut self, addr: u16) -> u8;
     fn write_rom(&mut self, addr: u16, val: u8);
@@ -6,6 +9,22 @@ pub trait ROM {
     // 0xA000 to 0xBFFF
     fn read_ram(&mut self, addr: u16) -> u8;
     fn write_ram(&mut self, addr: u16, val: u8);
+
+    // Gets the bank currently mapped to an address in either region
+    // Used for telling apart symbols that share an address
+    fn bank(&self, addr: u16) -> u16;
+
+    // The entire ROM, in the same order as the file
+    fn data(&self) -> &[u8];
+
+    // Cart RAM, which is empty if there isn't any
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    // Size of the ROM in bytes
+    fn size(&self) -> usize {
+        self.data().len()
+    }
 }
 
 pub fn rom_from_bytes(rom: &[u8]) -> Result<Box<dyn ROM>, String> {
@@ -60,6 +79,34 @@ impl ROM for NoMapper {
         0xFF
     }
     fn write_ram(&mut self, _addr: u16, _val: u8) {}
+
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x4000..=0x7FFF => 1,
+            _ => 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+// There's no mapper state to save
+impl State for NoMapper {
+    fn save(&self, _w: &mut StateWriter) {}
+
+    fn load(&mut self, _r: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 #[derive(Default)]
@@ -123,4 +170,44 @@ impl ROM for MBC1 {
             bank[addr as usize & 0x1FFF] = val;
         }
     }
+
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x4000..=0x7FFF => (self.rom_bank_idx.max(1) as usize % self.rom_banks.len()) as u16,
+            0xA000..=0xBFFF if !self.ram_banks.is_empty() => {
+                (self.ram_bank_idx.max(1) as usize % self.ram_banks.len()) as u16
+            }
+            _ => 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        self.rom_banks.as_flattened()
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.ram_banks.as_flattened()
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.ram_banks.as_flattened_mut()
+    }
+}
+
+// ROM banks don't need to be saved since they never change
+impl State for MBC1 {
+    fn save(&self, w: &mut StateWriter) {
+        self.ram_banks.as_slice().save(w);
+        self.ram_enabled.save(w);
+        self.rom_bank_idx.save(w);
+        self.ram_bank_idx.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.ram_banks.as_mut_slice().load(r)?;
+        self.ram_enabled.load(r)?;
+        self.rom_bank_idx.load(r)?;
+        self.ram_bank_idx.load(r)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,61 @@
+use crate::{gb::FrameInfo, Gameboy};
+
+// Cuts input latency by showing a frame from slightly in the future
+// After each real frame, the state is saved, some more frames are run with the same input, and
+// the framebuffer from the last of those is kept before going back to the saved state
+// Serial output isn't part of save states, so it gets put back separately
+// Only the real frames affect the Gameboy, so anything taken from it after run_frame or update
+// (e.g. serial output or rewind snapshots) is the same as without running ahead
+pub struct RunAhead {
+    // How many frames ahead to run, where 0 disables this
+    pub frames: u32,
+    // What should be shown for the last frame
+    pub framebuffer: Box<[u8; 160 * 144]>,
+    // Which palette each pixel of framebuffer used, see PPU::framebuffer_palettes
+    pub framebuffer_palettes: Box<[u8; 160 * 144]>,
+}
+
+impl RunAhead {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            framebuffer: Box::new([0; 160 * 144]),
+            framebuffer_palettes: Box::new([0; 160 * 144]),
+        }
+    }
+
+    // Runs a real frame, then runs ahead
+    pub fn run_frame(&mut self, gb: &mut Gameboy) -> Result<FrameInfo, String> {
+        let info = gb.run_frame();
+        self.update(gb)?;
+        Ok(info)
+    }
+
+    // Runs ahead from a frame that was just run some other way (e.g. by a movie)
+    // The saved state only fails to load if it doesn't match what save_state wrote (which would
+    // be a bug), and then the Gameboy is left on the last frame it ran ahead to
+    pub fn update(&mut self, gb: &mut Gameboy) -> Result<(), String> {
+        if self.frames == 0 {
+            self.copy_framebuffer(gb);
+            return Ok(());
+        }
+
+        let state = gb.save_state();
+        let serial_output = gb.components.serial.output.clone();
+        for _ in 0..self.frames {
+            gb.run_frame();
+        }
+        self.copy_framebuffer(gb);
+
+        gb.components.serial.output = serial_output;
+        gb.load_state(&state)
+    }
+
+    // Takes the framebuffer that should be shown directly from the Gameboy
+    pub fn copy_framebuffer(&mut self, gb: &Gameboy) {
+        let ppu = &gb.components.ppu;
+        self.framebuffer.copy_from_slice(&ppu.framebuffer);
+        self.framebuffer_palettes
+            .copy_from_slice(&ppu.framebuffer_palettes);
+    }
+}
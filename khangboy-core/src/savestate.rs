@@ -0,0 +1,203 @@
+// Save state serialization
+// States are a small header followed by every component's fields in a fixed order, all
+// little-endian with no padding or field names:
+//   "KBST" magic, u32 format version, u64 hash of the ROM
+// Anything that changes the field order or adds fields needs STATE_VERSION to be bumped
+
+pub const STATE_MAGIC: [u8; 4] = *b"KBST";
+pub const STATE_VERSION: u32 = 12;
+
+#[derive(Default)]
+pub struct StateWriter {
+    pub buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn write(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+}
+
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn read(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() < len {
+            return Err("State is truncated".into());
+        }
+        let (data, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(data)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.read(N)?.try_into().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+// Anything that can be saved to and loaded from a state
+pub trait State {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String>;
+
+    // Saves only what affects emulation, leaving out output like framebuffers
+    // This is for comparing states (e.g. to catch movie desyncs), and can't be loaded
+    fn save_machine(&self, w: &mut StateWriter) {
+        self.save(w);
+    }
+}
+
+// Implements State for a struct by saving and loading the listed fields in order
+// Fields marked with #[output] are left out of save_machine
+// A function can be given after the fields and a semicolon to check what was loaded, so that a
+// corrupted state gets rejected instead of causing a panic later on
+macro_rules! impl_state {
+    (@machine #[output] $field:expr, $w:ident) => {};
+    (@machine $field:expr, $w:ident) => {
+        $crate::savestate::State::save_machine(&$field, $w)
+    };
+    ($ty:ty { $($(#[$attr:ident])? $field:ident),* $(,)? } $(; $check:path)?) => {
+        impl $crate::savestate::State for $ty {
+            fn save(&self, w: &mut $crate::savestate::StateWriter) {
+                $($crate::savestate::State::save(&self.$field, w);)*
+            }
+
+            fn save_machine(&self, w: &mut $crate::savestate::StateWriter) {
+                $($crate::savestate::impl_state!(@machine $(#[$attr])? self.$field, w);)*
+            }
+
+            fn load(&mut self, r: &mut $crate::savestate::StateReader) -> Result<(), String> {
+                $($crate::savestate::State::load(&mut self.$field, r)?;)*
+                $($check(self)?;)?
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use impl_state;
+
+macro_rules! impl_state_int {
+    ($($ty:ty),*) => {
+        $(impl State for $ty {
+            fn save(&self, w: &mut StateWriter) {
+                w.write(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+                *self = <$ty>::from_le_bytes(r.read_array()?);
+                Ok(())
+            }
+        })*
+    };
+}
+impl_state_int!(u8, u16, u32, u64, i16);
+
+// Saved as 64 bits so states are the same everywhere
+impl State for usize {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u64).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut val = 0u64;
+        val.load(r)?;
+        *self = val.try_into().map_err(|_| "Value doesn't fit in usize")?;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, w: &mut StateWriter) {
+        w.write(&[*self as u8]);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        *self = r.read_array::<1>()?[0] != 0;
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, w: &mut StateWriter) {
+        for x in self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for x in self {
+            x.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+// Saved with a length prefix, and the length has to match when loading
+// Used for things like cart RAM, where the size depends on the ROM
+impl<T: State> State for [T] {
+    fn save(&self, w: &mut StateWriter) {
+        self.len().save(w);
+        for x in self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut len = 0usize;
+        len.load(r)?;
+        if len != self.len() {
+            return Err("State doesn't match this cartridge's memory layout".into());
+        }
+        for x in self {
+            x.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+// Saved with a length prefix, resizing when loaded
+impl State for Vec<u8> {
+    fn save(&self, w: &mut StateWriter) {
+        self.len().save(w);
+        w.write(self);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut len = 0usize;
+        len.load(r)?;
+        *self = r.read(len)?.to_vec();
+        Ok(())
+    }
+}
+
+impl<T: State + ?Sized> State for Box<T> {
+    fn save(&self, w: &mut StateWriter) {
+        (**self).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        (**self).load(r)
+    }
+
+    fn save_machine(&self, w: &mut StateWriter) {
+        (**self).save_machine(w);
+    }
+}
+
+// 64-bit FNV-1a, used to tell ROMs apart
+// https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF29CE484222325, |acc, &x| {
+        (acc ^ x as u64).wrapping_mul(0x100000001B3)
+    })
+}
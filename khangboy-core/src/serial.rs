@@ -1,27 +1,77 @@
-use std::io::Write;
+use crate::{savestate::impl_state, util::BitIndex};
 
 // Handles link port stuff, used for link cable and blargg CPU tests
+// Nothing is ever connected to the other end, so every received bit is 1
 #[derive(Default)]
-pub struct Serial;
+pub struct Serial {
+    data: u8,
+    control: u8,
+    transfer_cycles: u16,
+
+    // Bytes that have been sent out, which is how blargg's tests report results
+    // This isn't part of save states, and only the last MAX_OUTPUT bytes are kept unless it's
+    // drained with take_output
+    pub output: Vec<u8>,
+}
+
+const MAX_OUTPUT: usize = 0x1000;
 
 impl Serial {
+    // Ticks one M-cycle
+    pub fn tick(&mut self) -> bool {
+        // Only transfers using the internal clock progress, since there's no external clock source
+        if !self.control.test(7) || !self.control.test(0) {
+            return false;
+        }
+
+        // 8192 Hz internal clock, so each bit takes 128 M-cycles
+        // TODO: This should be derived from the DIV counter
+        self.transfer_cycles += 1;
+        if self.transfer_cycles == 128 * 8 {
+            self.data = 0xFF;
+            self.control = self.control.set(7, false);
+            self.transfer_cycles = 0;
+            return true;
+        }
+
+        false
+    }
+
     pub fn read_sb(&self) -> u8 {
-        // TODO
-        0xFF
+        self.data
     }
 
     pub fn write_sb(&mut self, val: u8) {
-        // TODO
-        //print!("{}", val as char);
-        //let _ = std::io::stdout().flush();
+        self.data = val;
     }
 
     pub fn read_sc(&self) -> u8 {
-        // TODO
-        0xFF
+        // Bits 6-1 always return 1
+        self.control | 0b0111_1110
     }
 
-    pub fn write_sc(&mut self, _val: u8) {
-        // TODO
+    pub fn write_sc(&mut self, val: u8) {
+        self.control = val & 0b1000_0001;
+        if self.control.test(7) {
+            // Transfers using an external clock never happen since nothing is connected
+            if self.control.test(0) {
+                if self.output.len() == MAX_OUTPUT {
+                    self.output.drain(..MAX_OUTPUT / 2);
+                }
+                self.output.push(self.data);
+            }
+            self.transfer_cycles = 0;
+        }
+    }
+
+    // Takes everything that's been sent out since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
     }
 }
+
+impl_state!(Serial {
+    data,
+    control,
+    transfer_cycles
+});
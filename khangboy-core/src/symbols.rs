@@ -0,0 +1,134 @@
+use std::path::Path;
+
+// Labels loaded from a .sym file, as written by RGBDS (rgblink -n) or wla-dx (-S)
+// Both use "bank:addr label" lines in hex, with wla-dx putting them under a [labels] section
+// Anything else (comments, other wla-dx sections, etc.) is ignored
+#[derive(Default)]
+pub struct SymbolTable {
+    // Every label, including aliases for the same address, so any of them can be found by name
+    symbols: Vec<Symbol>,
+    // One label per address in banked regions, sorted by bank, then address
+    banked: Vec<Symbol>,
+    // One label per address everywhere else, sorted by address
+    unbanked: Vec<Symbol>,
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Symbol {
+    bank: u16,
+    addr: u16,
+    name: String,
+}
+
+impl SymbolTable {
+    pub fn parse(text: &str) -> Self {
+        let mut symbols = Vec::new();
+        let mut in_labels = true;
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap().trim();
+            if let Some(section) = line.strip_prefix('[') {
+                in_labels = section.starts_with("labels]");
+                continue;
+            }
+            if !in_labels {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(loc), Some(name)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let Some((bank, addr)) = loc.split_once(':') else {
+                continue;
+            };
+            if let (Ok(bank), Ok(addr)) =
+                (u16::from_str_radix(bank, 16), u16::from_str_radix(addr, 16))
+            {
+                symbols.push(Symbol {
+                    bank,
+                    addr,
+                    name: name.to_string(),
+                });
+            }
+        }
+        symbols.sort();
+
+        // Aliases are dropped here, leaving the first one by name to show up in addresses
+        let (mut banked, mut unbanked): (Vec<_>, Vec<_>) =
+            symbols.iter().cloned().partition(|x| banked_region(x.addr));
+        banked.dedup_by(|x, y| x.bank == y.bank && x.addr == y.addr);
+        unbanked.sort_by(|x, y| x.addr.cmp(&y.addr).then_with(|| x.name.cmp(&y.name)));
+        unbanked.dedup_by(|x, y| x.addr == y.addr);
+        Self {
+            symbols,
+            banked,
+            unbanked,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    // Finds the label at an exact address
+    pub fn get(&self, bank: u16, addr: u16) -> Option<&str> {
+        self.resolve(bank, addr)
+            .and_then(|(name, offset)| (offset == 0).then_some(name))
+    }
+
+    // Finds the closest label at or before an address, along with how far past it the address is
+    // Labels from other memory regions don't count, so the end of ROM0 doesn't show up as
+    // "SomeLabel+0x4321" in WRAM
+    pub fn resolve(&self, bank: u16, addr: u16) -> Option<(&str, u16)> {
+        let symbol = if banked_region(addr) {
+            let idx = self
+                .banked
+                .partition_point(|x| (x.bank, x.addr) <= (bank, addr));
+            self.banked[..idx].last().filter(|x| x.bank == bank)?
+        } else {
+            let idx = self.unbanked.partition_point(|x| x.addr <= addr);
+            self.unbanked[..idx].last()?
+        };
+        (region(symbol.addr) == region(addr)).then(|| (symbol.name.as_str(), addr - symbol.addr))
+    }
+
+    // Finds the bank and address of a label
+    pub fn find(&self, name: &str) -> Option<(u16, u16)> {
+        self.symbols
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| (x.bank, x.addr))
+    }
+
+    // Formats an address as "Label" or "Label+offset" if possible, otherwise as "bank:addr"
+    pub fn format(&self, bank: u16, addr: u16) -> String {
+        match self.resolve(bank, addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{name}+{offset:#x}"),
+            None => format!("{bank:02X}:{addr:04X}"),
+        }
+    }
+}
+
+// The bank only matters where the cart can switch banks
+// Everything else has one bank on the DMG, even if the assembler calls part of WRAM bank 1
+fn banked_region(addr: u16) -> bool {
+    matches!(addr, 0x4000..=0x7FFF | 0xA000..=0xBFFF)
+}
+
+fn region(addr: u16) -> u8 {
+    match addr {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xDFFF => 4,
+        0xFE00..=0xFE9F => 5,
+        0xFF80..=0xFFFE => 6,
+        _ => 7,
+    }
+}
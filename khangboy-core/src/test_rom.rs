@@ -0,0 +1,138 @@
+use crate::{bus::Bus, Gameboy};
+
+// The result reported by a test ROM
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TestResult {
+    Passed,
+    Failed(String),
+    // Nothing was reported before running out of cycles
+    Timeout,
+}
+
+// Mooneye's tests execute LD B,B as a software breakpoint when they're done
+const LD_B_B: u8 = 0x40;
+
+// Mooneye's tests load B, C, D, E, H, and L with these before the breakpoint
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+// blargg's tests write this to 0xA001-0xA003 if they report their result through cart RAM
+// See https://github.com/retrio/gb-test-roms/blob/master/README.md
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+
+// How often cart RAM gets checked for a result, in M-cycles
+const MEMORY_POLL_INTERVAL: u64 = 0x1000;
+
+// Runs a test ROM for up to max_cycles M-cycles or until it reports a result
+// Results are detected through blargg's serial output, blargg's 0xA000 memory protocol,
+// and Mooneye's register signature
+pub fn run_test_rom(gb: &mut Gameboy, max_cycles: u64) -> TestResult {
+    run_test_rom_with(gb, max_cycles, |gb| {
+        gb.cpu.step(&mut gb.components);
+    })
+}
+
+// Same as run_test_rom, but calls step to execute every instruction
+// This way, instructions can be logged or stepped through something like a profiler
+pub fn run_test_rom_with(
+    gb: &mut Gameboy,
+    max_cycles: u64,
+    mut step: impl FnMut(&mut Gameboy),
+) -> TestResult {
+    let start_cycle = gb.components.cycle;
+    let mut next_poll = start_cycle;
+    let mut serial_output = Vec::new();
+    while gb.components.cycle - start_cycle < max_cycles {
+        step(gb);
+
+        if gb.cpu.next_opcode() == LD_B_B {
+            let cpu = &gb.cpu;
+            let regs = [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l];
+            if regs == MOONEYE_PASS {
+                return TestResult::Passed;
+            } else if regs == MOONEYE_FAIL {
+                return TestResult::Failed("Mooneye failure signature".into());
+            }
+        }
+
+        let output = gb.components.serial.take_output();
+        if !output.is_empty() {
+            serial_output.extend_from_slice(&output);
+            if let Some(result) = check_serial(&serial_output) {
+                return result;
+            }
+        }
+
+        if gb.components.cycle >= next_poll {
+            next_poll = gb.components.cycle + MEMORY_POLL_INTERVAL;
+            if let Some(result) = check_memory(gb) {
+                return result;
+            }
+        }
+    }
+
+    TestResult::Timeout
+}
+
+// Runs until LD B,B is executed, returning false if that didn't happen within max_cycles M-cycles
+// Screenshot tests like dmg-acid2 use this to signal that the screen is ready to be checked
+pub fn run_until_breakpoint(gb: &mut Gameboy, max_cycles: u64) -> bool {
+    let start_cycle = gb.components.cycle;
+    while gb.components.cycle - start_cycle < max_cycles {
+        gb.cpu.step(&mut gb.components);
+        if gb.cpu.next_opcode() == LD_B_B {
+            return true;
+        }
+    }
+    false
+}
+
+// blargg's tests print "Passed" or "Failed" on a line after the test name
+fn check_serial(output: &[u8]) -> Option<TestResult> {
+    // Wait for the whole line to come in so the failure message isn't cut off
+    if output.last() != Some(&b'\n') {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(output);
+    if text.contains("Passed") {
+        Some(TestResult::Passed)
+    } else if text.contains("Failed") {
+        Some(TestResult::Failed(text.trim().into()))
+    } else {
+        None
+    }
+}
+
+// blargg's tests can also write a result code to 0xA000 and a null-terminated message to 0xA004
+fn check_memory(gb: &mut Gameboy) -> Option<TestResult> {
+    let com = &mut gb.components;
+    let signature = [
+        com.read_passive(0xA001),
+        com.read_passive(0xA002),
+        com.read_passive(0xA003),
+    ];
+    if signature != BLARGG_SIGNATURE {
+        return None;
+    }
+
+    match com.read_passive(0xA000) {
+        BLARGG_RUNNING => None,
+        0x00 => Some(TestResult::Passed),
+        code => {
+            let mut text = Vec::new();
+            for addr in 0xA004..=0xBFFF {
+                match com.read_passive(addr) {
+                    0 => break,
+                    x => text.push(x),
+                }
+            }
+            let text = String::from_utf8_lossy(&text);
+            Some(TestResult::Failed(format!(
+                "Result code {code:#04x}: {}",
+                text.trim()
+            )))
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::util::BitIndex;
+use crate::{savestate::impl_state, util::BitIndex};
 
 // Keeps track of cycles
 #[derive(Default)]
@@ -76,3 +76,11 @@ impl Timer {
         self.control = val;
     }
 }
+
+impl_state!(Timer {
+    clocks,
+    counter,
+    modulo,
+    control,
+    edge_delay
+});
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{synthetic_rom, synthetic_rom_with};
+use khangboy_core::cdl::{CodeDataLog, CDL_CODE, CDL_DATA, CDL_OPCODE};
+
+#[test]
+fn logs_code_and_data() {
+    // LD A,($0150); JR -5
+    let mut gb = synthetic_rom_with(&[(0x100, &[0xFA, 0x50, 0x01, 0x18, 0xFB]), (0x150, &[0x42])]);
+    let mut cdl = CodeDataLog::new(&gb);
+    cdl.run(&mut gb, 100);
+
+    assert_eq!(
+        cdl.flags[0x100..0x106],
+        [
+            CDL_CODE | CDL_OPCODE,
+            CDL_CODE,
+            CDL_CODE,
+            CDL_CODE | CDL_OPCODE,
+            CDL_CODE,
+            0
+        ]
+    );
+    assert_eq!(cdl.flags[0x150], CDL_DATA);
+    assert_eq!(cdl.coverage(), (5, 1));
+}
+
+#[test]
+fn saves_and_loads() {
+    let mut gb = synthetic_rom(&[0x18, 0xFE]);
+    let mut cdl = CodeDataLog::new(&gb);
+    cdl.run(&mut gb, 100);
+
+    let path = std::env::temp_dir().join(format!("khangboy-cdl-{}.cdl", std::process::id()));
+    cdl.save(&path).unwrap();
+    let loaded = CodeDataLog::load(&gb, &path).unwrap();
+    assert_eq!(loaded.flags, cdl.flags);
+
+    // Logs for a different ROM size get rejected
+    std::fs::write(&path, [0; 0x100]).unwrap();
+    assert!(CodeDataLog::load(&gb, &path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
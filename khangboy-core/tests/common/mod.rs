@@ -0,0 +1,22 @@
+// Helpers shared between the integration tests, which don't all use every one
+#![allow(dead_code)]
+
+use khangboy_core::{bus::Bus, rom::rom_from_bytes, Gameboy};
+
+// Makes a ROM that runs code at 0x100, skipping the bootrom since there's no logo for it to check
+pub fn synthetic_rom(code: &[u8]) -> Gameboy {
+    synthetic_rom_with(&[(0x100, code)])
+}
+
+// Same as synthetic_rom, but with code at other addresses too
+pub fn synthetic_rom_with(code: &[(u16, &[u8])]) -> Gameboy {
+    let mut rom = vec![0; 0x8000];
+    for &(addr, code) in code {
+        let addr = addr as usize;
+        rom[addr..addr + code.len()].copy_from_slice(code);
+    }
+    let mut gb = Gameboy::new(rom_from_bytes(&rom).unwrap());
+    gb.components.write_passive(0xFF50, 1);
+    gb.cpu.set_instruction_addr(&mut gb.components, 0x100);
+    gb
+}
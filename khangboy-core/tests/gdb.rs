@@ -0,0 +1,143 @@
+// Talks to the GDB stub over a local socket like GDB would
+
+mod common;
+
+use common::synthetic_rom;
+use khangboy_core::{gdb::GdbStub, Gameboy};
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    fn send(&mut self, data: &[u8]) {
+        let checksum = data.iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{checksum:02x}").as_bytes());
+        self.stream.write_all(&packet).unwrap();
+    }
+
+    // Waits for the next packet, skipping acks
+    fn receive(&mut self) -> Vec<u8> {
+        loop {
+            if let Some(start) = self.buf.iter().position(|&x| x == b'$') {
+                if let Some(end) = self.buf[start..].iter().position(|&x| x == b'#') {
+                    let end = start + end;
+                    if end + 2 < self.buf.len() {
+                        let data = self.buf[start + 1..end].to_vec();
+                        self.buf.drain(..end + 3);
+                        return data;
+                    }
+                }
+            }
+            let mut temp = [0u8; 0x100];
+            let n = self.stream.read(&mut temp).expect("no reply from the stub");
+            assert_ne!(n, 0, "stub disconnected");
+            self.buf.extend_from_slice(&temp[..n]);
+        }
+    }
+}
+
+fn connect() -> (GdbStub, Client) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let gdb = GdbStub::from_stream(listener.accept().unwrap().0).unwrap();
+    (
+        gdb,
+        Client {
+            stream,
+            buf: Vec::new(),
+        },
+    )
+}
+
+// Sends a command and lets the stub handle it
+fn command(gdb: &mut GdbStub, gb: &mut Gameboy, client: &mut Client, data: &[u8]) -> Vec<u8> {
+    client.send(data);
+    gdb.run(gb, 0);
+    client.receive()
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    let (mut gdb, mut client) = connect();
+    let mut gb = synthetic_rom(&[0x18, 0xFE]);
+
+    let malformed: &[&[u8]] = &[
+        // Not ASCII
+        b"\xff",
+        "pé".as_bytes(),
+        "m\u{100}0,1".as_bytes(),
+        // Bad register values
+        b"P0=zz00",
+        "P0=é0".as_bytes(),
+        b"P0=123",
+        b"G00",
+        // Odd length or invalid memory data
+        b"Mc000,1:a",
+        b"Mc000,2:abc",
+        b"Mc000,1:zz",
+        b"Mc000,1:+1",
+        // Odd length monitor command
+        b"qRcmd,abc",
+    ];
+    for &data in malformed {
+        let reply = command(&mut gdb, &mut gb, &mut client, data);
+        assert!(reply.starts_with(b"E"), "{data:?} got {reply:?}");
+    }
+
+    // Still works afterwards
+    assert_eq!(
+        command(&mut gdb, &mut gb, &mut client, b"Mc000,2:abcd"),
+        b"OK"
+    );
+    assert_eq!(command(&mut gdb, &mut gb, &mut client, b"mc000,2"), b"abcd");
+    assert_eq!(command(&mut gdb, &mut gb, &mut client, b"P1=3412"), b"OK");
+    assert_eq!(command(&mut gdb, &mut gb, &mut client, b"p1"), b"3412");
+}
+
+#[test]
+fn read_watchpoints_ignore_instruction_fetches() {
+    let (mut gdb, mut client) = connect();
+    // NOP; NOP; NOP; LD A,($C000); JR -2
+    let mut gb = synthetic_rom(&[0x00, 0x00, 0x00, 0xFA, 0x00, 0xC0, 0x18, 0xFE]);
+
+    assert_eq!(
+        command(&mut gdb, &mut gb, &mut client, b"Z3,0100,10"),
+        b"OK"
+    );
+    assert_eq!(command(&mut gdb, &mut gb, &mut client, b"Z3,c000,1"), b"OK");
+    client.send(b"c");
+    gdb.run(&mut gb, 1000);
+    assert_eq!(client.receive(), b"T05rwatch:c000;");
+}
+
+#[test]
+fn keeps_running_after_gdb_disconnects() {
+    let (mut gdb, mut client) = connect();
+    // INC A; JR -3
+    let mut gb = synthetic_rom(&[0x3C, 0x18, 0xFD]);
+
+    assert_eq!(command(&mut gdb, &mut gb, &mut client, b"Z0,0100,1"), b"OK");
+    client.send(b"c");
+    drop(client);
+
+    // Hitting the breakpoint tries to tell GDB, which is gone by now
+    for _ in 0..10 {
+        gdb.run(&mut gb, 1000);
+    }
+    assert!(!gdb.attached());
+    let a = gb.cpu.a;
+    assert!(gdb.run(&mut gb, 1000) >= 1000);
+    assert_ne!(gb.cpu.a, a);
+}
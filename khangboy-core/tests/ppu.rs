@@ -0,0 +1,448 @@
+// Timing details of the PPU that test ROMs don't cover, checked with small hand-written programs
+
+mod common;
+
+use common::{synthetic_rom, synthetic_rom_with};
+use khangboy_core::{bus::Bus, gb::MAX_FRAME_CYCLES, Gameboy};
+
+// LY and the STAT mode
+fn position(gb: &mut Gameboy) -> (u8, u8) {
+    let ly = gb.components.read_passive(0xFF44);
+    (ly, gb.components.read_passive(0xFF41) & 3)
+}
+
+// Runs NOPs one M-cycle at a time until LY and the STAT mode are what's wanted
+fn run_until(gb: &mut Gameboy, wanted: (u8, u8)) {
+    for _ in 0..20000 {
+        if position(gb) == wanted {
+            return;
+        }
+        gb.run(1);
+    }
+    panic!("never got to {wanted:?}");
+}
+
+// Just NOPs, with tile 0 filled with color 3 so the background isn't blank
+fn solid_bg() -> Gameboy {
+    let mut gb = synthetic_rom(&[]);
+    for addr in 0x8000..0x8010 {
+        gb.components.write_passive(addr, 0xFF);
+    }
+    gb
+}
+
+// Runs code at 0x150 with 10 objects on the first few lines, spread out so each one costs as much
+// as it can
+fn with_objects(code: &[u8]) -> Gameboy {
+    // JP $150
+    let mut gb = synthetic_rom_with(&[(0x100, &[0xC3, 0x50, 0x01]), (0x150, code)]);
+    for i in 0..10 {
+        gb.components.write_passive(0xFE00 + i * 4, 16);
+        gb.components
+            .write_passive(0xFE00 + i * 4 + 1, 8 + i as u8 * 16);
+    }
+    gb
+}
+
+#[test]
+fn window_toggling_cuts_mode3_short() {
+    // LD A,7; LDH (SCX),A; LDH (WX),A; LD HL,LCDC; LD B,$B3; LD C,$93
+    let mut code = vec![
+        0x3E, 0x07, 0xE0, 0x43, 0xE0, 0x4B, 0x21, 0x40, 0xFF, 0x06, 0xB3, 0x0E, 0x93,
+    ];
+    // Turn the window on and off as fast as possible: LD (HL),B; LD (HL),C; ...; JR loop
+    for _ in 0..32 {
+        code.extend_from_slice(&[0x70, 0x71]);
+    }
+    code.extend_from_slice(&[0x18, (-66i8) as u8]);
+    let mut gb = with_objects(&code);
+    gb.run_frame();
+    gb.run_frame();
+    assert!(gb.components.ppu.mode3_overruns > 0);
+
+    // Everything else at once still fits: LD A,7; LDH (SCX),A; LDH (WX),A; LD A,$B3;
+    // LDH (LCDC),A; JR -2
+    let mut gb = with_objects(&[
+        0x3E, 0x07, 0xE0, 0x43, 0xE0, 0x4B, 0x3E, 0xB3, 0xE0, 0x40, 0x18, 0xFE,
+    ]);
+    gb.run_frame();
+    gb.run_frame();
+    assert_eq!(gb.components.ppu.mode3_overruns, 0);
+}
+
+#[test]
+fn lcd_off_and_back_on() {
+    let mut gb = solid_bg();
+    gb.components.write_passive(0xFF40, 0x91);
+    gb.run_frame();
+    gb.run_frame();
+    assert_eq!(gb.components.ppu.framebuffer[0], 3);
+
+    // Turning it off goes to line 0 in mode 0, with a blank screen
+    gb.run(1000);
+    gb.components.write_passive(0xFF40, 0x11);
+    assert_eq!(position(&mut gb), (0, 0));
+    assert!(gb.components.ppu.framebuffer.iter().all(|&x| x == 0));
+    let frame = gb.components.ppu.frame_count;
+    gb.run(20000);
+    assert_eq!(position(&mut gb), (0, 0));
+    assert_eq!(gb.components.ppu.frame_count, frame + 1);
+
+    // The first line skips OAM scan, staying in mode 0 until mode 3, and is 4 dots shorter
+    gb.components.write_passive(0xFF40, 0x91);
+    gb.run(18);
+    assert_eq!(position(&mut gb), (0, 0));
+    gb.run(1);
+    assert_eq!(position(&mut gb), (0, 3));
+    gb.run(93);
+    assert_eq!(position(&mut gb), (0, 0));
+    gb.run(1);
+    assert_eq!(position(&mut gb), (1, 2));
+
+    // That frame isn't shown, but the next one is
+    gb.run_frame();
+    assert_eq!(gb.components.ppu.framebuffer[0], 0);
+    gb.run_frame();
+    assert_eq!(gb.components.ppu.framebuffer[0], 3);
+}
+
+#[test]
+fn stat_interrupt_on_rising_edge_only() {
+    let stat_requested = |gb: &mut Gameboy| {
+        // The request shows up on the M-cycle after the line goes high
+        gb.run(1);
+        let requested = gb.components.read_passive(0xFF0F) & 0x02 != 0;
+        gb.components.write_passive(0xFF0F, 0);
+        requested
+    };
+
+    let mut gb = solid_bg();
+    gb.components.write_passive(0xFF40, 0x91);
+    gb.components.write_passive(0xFF45, 5);
+    gb.run_frame();
+
+    // LY=LYC and mode 0 interrupts, where mode 0 starts while LY=LYC is still true
+    gb.components.write_passive(0xFF41, 0x48);
+    run_until(&mut gb, (5, 2));
+    assert!(stat_requested(&mut gb));
+    run_until(&mut gb, (5, 0));
+    assert!(!stat_requested(&mut gb));
+    // On the next line, the line went low in between
+    run_until(&mut gb, (6, 0));
+    assert!(stat_requested(&mut gb));
+
+    // Mode 0 alone
+    gb.components.write_passive(0xFF41, 0x08);
+    run_until(&mut gb, (7, 0));
+    assert!(stat_requested(&mut gb));
+    run_until(&mut gb, (8, 2));
+    assert!(!stat_requested(&mut gb));
+
+    // Writing STAT during mode 0 or 1 briefly enables every source on the DMG
+    gb.components.write_passive(0xFF41, 0x00);
+    run_until(&mut gb, (8, 0));
+    assert!(!stat_requested(&mut gb));
+    gb.components.write_passive(0xFF41, 0x00);
+    assert!(stat_requested(&mut gb));
+    run_until(&mut gb, (9, 3));
+    gb.components.write_passive(0xFF41, 0x00);
+    assert!(!stat_requested(&mut gb));
+}
+
+// M-cycles spent in mode 3 on line 2, with objects on that line at some X positions
+fn mode3_length(scx: u8, objs: &[u8]) -> u64 {
+    let mut gb = synthetic_rom(&[]);
+    for (i, &x) in objs.iter().enumerate() {
+        gb.components.write_passive(0xFE00 + i as u16 * 4, 16);
+        gb.components.write_passive(0xFE00 + i as u16 * 4 + 1, x);
+    }
+    gb.components.write_passive(0xFF43, scx);
+    gb.components.write_passive(0xFF40, 0x93);
+    gb.run_frame();
+    run_until(&mut gb, (2, 3));
+    let start = gb.components.cycle();
+    run_until(&mut gb, (2, 0));
+    gb.components.cycle() - start
+}
+
+#[test]
+fn mode3_length_with_scrolling_and_objects() {
+    // 172 dots, plus the fine scroll, plus 6-11 for each object depending on where it is
+    // Only whole M-cycles can be seen here, so these are rounded up
+    assert_eq!(mode3_length(0, &[]), 43);
+    assert_eq!(mode3_length(4, &[]), 44);
+    assert_eq!(mode3_length(7, &[]), 45);
+    assert_eq!(mode3_length(0, &[8]), 46);
+    assert_eq!(mode3_length(0, &[12]), 45);
+    // Objects that are partly offscreen still count, but not ones that are entirely past the right
+    assert_eq!(mode3_length(0, &[0]), 46);
+    assert_eq!(mode3_length(0, &[168]), 43);
+    // After the first one, objects at the same X take 6 dots each, up to 10 of them
+    assert_eq!(mode3_length(0, &[8, 8]), 48);
+    assert_eq!(mode3_length(0, &[8; 10]), 60);
+    assert_eq!(mode3_length(0, &[8; 11]), 60);
+}
+
+#[test]
+fn object_priority() {
+    // Draws two objects on line 0, one in color 1 and the other in color 2
+    let draw = |first_x: u8, second_x: u8| {
+        let mut gb = synthetic_rom(&[]);
+        for i in 0..8 {
+            gb.components.write_passive(0x8010 + i * 2, 0xFF);
+            gb.components.write_passive(0x8020 + i * 2 + 1, 0xFF);
+        }
+        for (i, (x, tile)) in [(first_x, 1), (second_x, 2)].into_iter().enumerate() {
+            let addr = 0xFE00 + i as u16 * 4;
+            gb.components.write_passive(addr, 16);
+            gb.components.write_passive(addr + 1, x);
+            gb.components.write_passive(addr + 2, tile);
+        }
+        gb.components.write_passive(0xFF47, 0xE4);
+        gb.components.write_passive(0xFF48, 0xE4);
+        gb.components.write_passive(0xFF40, 0x93);
+        gb.run_frame();
+        gb.run_frame();
+        gb.components.ppu.framebuffer[..16].to_vec()
+    };
+
+    // The one further left wins where they overlap, even if it comes later in OAM
+    assert_eq!(
+        draw(12, 8),
+        [2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        draw(8, 12),
+        [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0]
+    );
+    // With the same X, the one that comes first in OAM wins
+    assert_eq!(draw(8, 8), [1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn window_line_counter_and_position() {
+    // The window's first row of tiles is color 1 and the second is color 2
+    // Tile 3 is color 1 only in its right half
+    let mut gb = synthetic_rom(&[]);
+    for i in 0..8 {
+        gb.components.write_passive(0x8010 + i * 2, 0xFF);
+        gb.components.write_passive(0x8020 + i * 2 + 1, 0xFF);
+        gb.components.write_passive(0x8030 + i * 2, 0x0F);
+    }
+    for i in 0..32 {
+        gb.components.write_passive(0x9C00 + i, 1);
+        gb.components.write_passive(0x9C20 + i, 2);
+    }
+    gb.components.write_passive(0xFF47, 0xE4);
+    gb.components.write_passive(0xFF4B, 7);
+    gb.components.write_passive(0xFF40, 0xF1);
+    gb.run_frame();
+
+    // Turn the window off for lines 4-11, which shouldn't use up any of its lines
+    run_until(&mut gb, (3, 0));
+    gb.components.write_passive(0xFF40, 0xD1);
+    run_until(&mut gb, (11, 0));
+    gb.components.write_passive(0xFF40, 0xF1);
+    gb.run_frame();
+    let fb = gb.components.ppu.framebuffer;
+    assert_eq!(fb[3 * 160], 1);
+    assert_eq!(fb[4 * 160], 0);
+    assert_eq!(fb[11 * 160], 0);
+    // Line 12 draws the window's line 4, so it only gets to the second row of tiles on line 16
+    assert_eq!(fb[12 * 160], 1);
+    assert_eq!(fb[15 * 160], 1);
+    assert_eq!(fb[16 * 160], 2);
+
+    // With WX below 7, the left of the window is cut off
+    gb.components.write_passive(0x9C00, 3);
+    gb.run_frame();
+    assert_eq!(gb.components.ppu.framebuffer[..8], [0, 0, 0, 0, 1, 1, 1, 1]);
+    gb.components.write_passive(0xFF4B, 3);
+    gb.run_frame();
+    assert_eq!(gb.components.ppu.framebuffer[..8], [1, 1, 1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn oam_dma_timing_and_bus_conflicts() {
+    // Runs from HRAM like games do, with WRAM at C000 counting up from 0x10 and the LCD off so
+    // OAM is accessible
+    // LDH (DMA),A; LD B,(HL); LD HL,$C050; LD E,(HL); JR -2
+    let start_dma = || {
+        let mut gb = synthetic_rom(&[]);
+        for i in 0..0xA0 {
+            gb.components.write_passive(0xC000 + i, 0x10 + i as u8);
+            gb.components.write_passive(0xFE00 + i, 0x55);
+        }
+        let code = [0xE0, 0x46, 0x46, 0x21, 0x50, 0xC0, 0x5E, 0x18, 0xFE];
+        for (i, &x) in code.iter().enumerate() {
+            gb.components.write_passive(0xFF80 + i as u16, x);
+        }
+        gb.cpu.a = 0xC0;
+        gb.cpu.h = 0xFE;
+        gb.cpu.l = 0x00;
+        gb.cpu.set_instruction_addr(&mut gb.components, 0xFF80);
+        gb.cpu.step(&mut gb.components);
+        gb
+    };
+
+    // Nothing happens on the M-cycle after the write (which fetched the next opcode), then the
+    // first byte gets copied
+    let mut gb = start_dma();
+    assert!(!gb.components.ppu.oam_dma_running);
+    assert_eq!(gb.components.read_passive(0xFE00), 0x55);
+    gb.components.tick();
+    assert!(gb.components.ppu.oam_dma_running);
+    assert_eq!(gb.components.read_passive(0xFE00), 0x10);
+    assert_eq!(gb.components.read_passive(0xFE01), 0x55);
+
+    // OAM can't be read while it's running, and reading WRAM gets whatever is being copied
+    // The read of C050 happens 5 M-cycles into the transfer, while it's copying C005
+    let mut gb = start_dma();
+    gb.cpu.step(&mut gb.components);
+    assert_eq!(gb.cpu.b, 0xFF);
+    gb.cpu.step(&mut gb.components);
+    gb.cpu.step(&mut gb.components);
+    assert_eq!(gb.cpu.e, 0x15);
+
+    // Everything gets there in the end
+    gb.run(200);
+    assert!(!gb.components.ppu.oam_dma_running);
+    for i in 0..0xA0 {
+        assert_eq!(gb.components.read_passive(0xFE00 + i), 0x10 + i as u8);
+    }
+}
+
+#[test]
+fn oam_bug_on_inc_rr() {
+    // JR -2 at 0x100, and INC HL; JR -2 at 0x4000
+    let mut gb = synthetic_rom_with(&[(0x100, &[0x18, 0xFE]), (0x4000, &[0x23, 0x18, 0xFE])]);
+    for i in 0..0xA0 {
+        gb.components
+            .write_passive(0xFE00 + i, (i as u8).wrapping_mul(37) ^ 0x5A);
+    }
+    gb.components.write_passive(0xFF40, 0x91);
+    gb.run_frame();
+    // OAM can only be read outside of modes 2 and 3
+    let oam_bytes = |gb: &mut Gameboy| -> Vec<u8> {
+        while position(gb).1 >= 2 {
+            gb.run(1);
+        }
+        (0..0xA0)
+            .map(|i| gb.components.read_passive(0xFE00 + i))
+            .collect()
+    };
+    let before = oam_bytes(&mut gb);
+    let inc_hl = |gb: &mut Gameboy, hl: u16| {
+        [gb.cpu.h, gb.cpu.l] = hl.to_be_bytes();
+        gb.cpu.set_instruction_addr(&mut gb.components, 0x4000);
+        gb.cpu.step(&mut gb.components);
+        gb.cpu.set_instruction_addr(&mut gb.components, 0x100);
+    };
+
+    // Only an address in OAM during mode 2 does anything
+    run_until(&mut gb, (5, 2));
+    inc_hl(&mut gb, 0xC000);
+    run_until(&mut gb, (5, 3));
+    inc_hl(&mut gb, 0xFE00);
+    assert_eq!(oam_bytes(&mut gb), before);
+
+    // Then one row of 8 bytes gets mixed with the one before it
+    run_until(&mut gb, (6, 2));
+    inc_hl(&mut gb, 0xFE00);
+    let after = oam_bytes(&mut gb);
+    let changed: Vec<usize> = (0..20)
+        .filter(|&row| after[row * 8..row * 8 + 8] != before[row * 8..row * 8 + 8])
+        .collect();
+    assert_eq!(changed.len(), 1, "{changed:?}");
+    let row = changed[0] * 8;
+    let word = |i: usize| u16::from_le_bytes([before[i], before[i + 1]]);
+    let (a, b, c) = (word(row), word(row - 8), word(row - 4));
+    let mixed = (((a ^ c) & (b ^ c)) ^ c).to_le_bytes();
+    assert_eq!(after[row..row + 2], mixed);
+    assert_eq!(after[row + 2..row + 8], before[row - 6..row]);
+}
+
+// Writes to a PPU register with the CPU partway through mode 3 on line 10, returning that line
+fn write_mid_line(gb: &mut Gameboy, addr: u8, val: u8, delay: u64) -> Vec<u8> {
+    gb.components.write_passive(0xFF47, 0xE4);
+    gb.components.write_passive(0xFF40, 0x91);
+    gb.run_frame();
+    gb.run_frame();
+    run_until(gb, (10, 3));
+    gb.run(delay);
+    // LDH (addr),A
+    gb.components.write_passive(0xC000, 0xE0);
+    gb.components.write_passive(0xC001, addr);
+    gb.cpu.a = val;
+    gb.cpu.set_instruction_addr(&mut gb.components, 0xC000);
+    gb.cpu.step(&mut gb.components);
+    gb.cpu.set_instruction_addr(&mut gb.components, 0x100);
+    gb.run_frame();
+    gb.components.ppu.framebuffer[10 * 160..11 * 160].to_vec()
+}
+
+#[test]
+fn mid_line_scroll_writes() {
+    // Row N of tile 0 is color N & 3
+    let rows = || {
+        let mut gb = synthetic_rom(&[]);
+        for row in 0..8 {
+            gb.components
+                .write_passive(0x8000 + row * 2, if row & 1 != 0 { 0xFF } else { 0 });
+            gb.components
+                .write_passive(0x8001 + row * 2, if row & 2 != 0 { 0xFF } else { 0 });
+        }
+        gb
+    };
+    // Each tile takes 2 M-cycles to fetch, so a write 2 M-cycles later lands a tile later
+    // SCY is read again for the tile data, after SCX has been used to pick the tile, so it can take
+    // effect a tile sooner
+    for delay in 1..12 {
+        let line = write_mid_line(&mut rows(), 0x42, 1, delay);
+        let tile = (delay as usize).div_ceil(2);
+        assert!(
+            line[..tile * 8].iter().all(|&x| x == 2),
+            "{delay}: {line:?}"
+        );
+        assert!(
+            line[tile * 8..].iter().all(|&x| x == 3),
+            "{delay}: {line:?}"
+        );
+    }
+
+    // Background tiles alternate between color 1 and color 2
+    let columns = || {
+        let mut gb = synthetic_rom(&[]);
+        for i in 0..8 {
+            gb.components.write_passive(0x8010 + i * 2, 0xFF);
+            gb.components.write_passive(0x8020 + i * 2 + 1, 0xFF);
+        }
+        for i in 0..0x400 {
+            gb.components.write_passive(0x9800 + i, 1 + (i & 1) as u8);
+        }
+        gb
+    };
+    // Scrolling by a tile partway through repeats the tile before the write
+    for delay in 1..12 {
+        let line = write_mid_line(&mut columns(), 0x43, 8, delay);
+        let tile = delay as usize / 2 + 1;
+        let expected: Vec<u8> = (0..160)
+            .map(|x| {
+                let column = x / 8 + (x >= tile * 8) as usize;
+                1 + (column & 1) as u8
+            })
+            .collect();
+        assert_eq!(line, expected, "{delay}");
+    }
+}
+
+#[test]
+fn lcd_toggling_still_ends_frames() {
+    // loop: LD A,$91; LDH (LCDC),A; XOR A; LDH (LCDC),A; JR loop
+    let mut gb = synthetic_rom(&[0x3E, 0x91, 0xE0, 0x40, 0xAF, 0xE0, 0x40, 0x18, 0xF7]);
+    for _ in 0..3 {
+        let info = gb.run_frame();
+        assert_eq!(info.frame, 0);
+        assert!((MAX_FRAME_CYCLES..MAX_FRAME_CYCLES + 4).contains(&info.cycles));
+    }
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::synthetic_rom_with;
+use khangboy_core::{
+    profiler::{Profiler, Routine},
+    symbols::SymbolTable,
+};
+
+const OUTER: Routine = Routine {
+    bank: 0,
+    addr: 0x200,
+};
+const INNER: Routine = Routine {
+    bank: 0,
+    addr: 0x210,
+};
+
+#[test]
+fn records_nested_calls() {
+    let mut gb = synthetic_rom_with(&[
+        // CALL $0200; JR -5
+        (0x100, &[0xCD, 0x00, 0x02, 0x18, 0xFB]),
+        // CALL $0210; RET
+        (0x200, &[0xCD, 0x10, 0x02, 0xC9]),
+        // NOP; RET
+        (0x210, &[0x00, 0xC9]),
+    ]);
+    let mut profiler = Profiler::new();
+    profiler.run(&mut gb, 1000);
+    assert!(profiler.call_stack().len() <= 2);
+
+    let routines = profiler.routines();
+    let stats = |routine| routines.iter().find(|x| x.0 == routine).unwrap().1;
+    let (outer, inner) = (stats(OUTER), stats(INNER));
+    assert!(outer.calls > 10);
+    assert!(outer.calls - inner.calls <= 1);
+    // Each call spends about 10 M-cycles in the outer routine itself and 5 in the inner one
+    // The last call might not have finished yet
+    assert!(outer.exclusive.abs_diff(outer.calls * 10) <= 10);
+    assert!(inner.exclusive.abs_diff(inner.calls * 5) <= 5);
+    assert!(outer.inclusive.abs_diff(outer.calls * 15) <= 15);
+    assert!(inner.inclusive.abs_diff(inner.exclusive) <= 5);
+    let symbols = SymbolTable::parse("00:0200 Outer\n00:0210 Inner");
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded, &symbols).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    let mut stacks: Vec<_> = folded
+        .lines()
+        .map(|x| x.rsplit_once(' ').unwrap().0)
+        .collect();
+    stacks.sort();
+    assert_eq!(stacks, ["root", "root;Outer", "root;Outer;Inner"]);
+}
+
+#[test]
+fn keeps_going_after_loading_an_earlier_state() {
+    let mut gb = synthetic_rom_with(&[
+        // CALL $0200; JR -5
+        (0x100, &[0xCD, 0x00, 0x02, 0x18, 0xFB]),
+        // CALL $0210; RET
+        (0x200, &[0xCD, 0x10, 0x02, 0xC9]),
+        // NOP; RET
+        (0x210, &[0x00, 0xC9]),
+    ]);
+    let mut profiler = Profiler::new();
+    profiler.run(&mut gb, 100);
+    let state = gb.save_state();
+    profiler.run(&mut gb, 1003);
+
+    // Going back to an earlier cycle while inside a call, returning from a call started later
+    gb.load_state(&state).unwrap();
+    profiler.run(&mut gb, 1000);
+    let routines = profiler.routines();
+    let outer = routines.iter().find(|x| x.0 == OUTER).unwrap().1;
+    // Nothing gets more time than was actually run
+    assert!(outer.inclusive < 2200);
+    assert!(outer.exclusive < 2200);
+}
@@ -0,0 +1,65 @@
+use khangboy_core::rewind::{apply_delta, encode_delta};
+
+fn round_trip(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let delta = encode_delta(from, to);
+    assert_eq!(apply_delta(from, &delta).unwrap(), to);
+    delta
+}
+
+#[test]
+fn identical_states() {
+    let state: Vec<u8> = (0..1000).map(|x| x as u8).collect();
+    let delta = round_trip(&state, &state);
+    // Just the length and one run of unchanged bytes
+    assert!(delta.len() <= 6);
+}
+
+#[test]
+fn fully_different_states() {
+    let from: Vec<u8> = (0..1000).map(|x| x as u8).collect();
+    let to: Vec<u8> = from.iter().map(|x| !x).collect();
+    round_trip(&from, &to);
+}
+
+#[test]
+fn states_of_different_lengths() {
+    let short = [1, 2, 3, 0, 0, 4];
+    let long = [1, 2, 5, 0, 0, 4, 0, 0, 7, 8];
+    round_trip(&short, &long);
+    round_trip(&long, &short);
+    round_trip(&[], &long);
+    round_trip(&long, &[]);
+    // Anything past the end is treated as zeroes, which still has to come back
+    round_trip(&[9, 9], &[9, 9, 0, 0]);
+    round_trip(&[9, 9, 0, 0], &[9, 9]);
+}
+
+#[test]
+fn rejects_truncated_deltas() {
+    let from: Vec<u8> = (0..300).map(|x| (x / 7) as u8).collect();
+    let mut to = from.clone();
+    to[10] = 0xFF;
+    to[200..250].fill(0x42);
+    to.extend_from_slice(&[1, 2, 3]);
+
+    let delta = round_trip(&from, &to);
+    for len in 0..delta.len() {
+        assert!(apply_delta(&from, &delta[..len]).is_err(), "{len}");
+    }
+}
+
+#[test]
+fn rejects_corrupted_deltas() {
+    let from = [0; 16];
+    // Runs that go past the end of the state
+    assert!(apply_delta(&from, &[16, 10, 10]).is_err());
+    assert!(apply_delta(
+        &from,
+        &[16, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 1]
+    )
+    .is_err());
+    // More changed bytes than the delta has
+    assert!(apply_delta(&from, &[16, 0, 16, 1, 2]).is_err());
+    // A number that doesn't fit in usize
+    assert!(apply_delta(&from, &[0xFF; 20]).is_err());
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::synthetic_rom;
+use khangboy_core::runahead::RunAhead;
+
+// Runs a few frames of a ROM that keeps sending bytes over serial, returning everything sent
+fn serial_output(frames_ahead: u32) -> Vec<u8> {
+    // LD A,$81; LDH (SC),A; INC B; LD A,B; LDH (SB),A; loop: LDH A,(SC); RLA; JR C,loop; JR start
+    let mut gb = synthetic_rom(&[
+        0x3E, 0x81, 0xE0, 0x02, 0x04, 0x78, 0xE0, 0x01, 0xF0, 0x02, 0x17, 0x38, 0xFB, 0x18, 0xF1,
+    ]);
+    let mut run_ahead = RunAhead::new(frames_ahead);
+    for _ in 0..5 {
+        run_ahead.run_frame(&mut gb).unwrap();
+    }
+    gb.components.serial.take_output()
+}
+
+#[test]
+fn frames_ahead_dont_send_serial_output() {
+    let expected = serial_output(0);
+    assert!(expected.len() > 10);
+    assert_eq!(serial_output(1), expected);
+    assert_eq!(serial_output(3), expected);
+}
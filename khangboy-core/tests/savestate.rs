@@ -0,0 +1,186 @@
+mod common;
+
+use common::synthetic_rom;
+use khangboy_core::{bus::Bus, palette, Gameboy};
+use std::collections::HashSet;
+
+#[test]
+fn machine_state_hash_ignores_framebuffers() {
+    // LD A,$91; LDH (LCDC),A; JR -2
+    let mut gb = synthetic_rom(&[0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE]);
+    gb.run_frame();
+    let hash = gb.machine_state_hash();
+
+    // Frontends can draw over these (e.g. for run-ahead) without changing anything
+    gb.components.ppu.framebuffer.fill(3);
+    gb.components.ppu.framebuffer_palettes.fill(0xFF);
+    assert_eq!(gb.machine_state_hash(), hash);
+
+    gb.components.write_passive(0xC000, 1);
+    assert_ne!(gb.machine_state_hash(), hash);
+    gb.components.write_passive(0xC000, 0);
+    gb.cpu.a ^= 1;
+    assert_ne!(gb.machine_state_hash(), hash);
+}
+
+#[test]
+fn debug_layers_only_change_output() {
+    // LD A,$93; LDH (LCDC),A; JR -2
+    let new_gb = || {
+        let mut gb = synthetic_rom(&[0x3E, 0x93, 0xE0, 0x40, 0x18, 0xFE]);
+        // Tile 0 is solid color 3, and object 0 uses it at the top left
+        for addr in 0x8000..0x8010 {
+            gb.components.write_passive(addr, 0xFF);
+        }
+        for (i, val) in [16, 8, 0, 0].into_iter().enumerate() {
+            gb.components.write_passive(0xFE00 + i as u16, val);
+        }
+        gb
+    };
+
+    let mut shown = new_gb();
+    let mut hidden_bg = new_gb();
+    hidden_bg.components.ppu.debug_layers.hide_bg = true;
+    let mut hidden_obj = new_gb();
+    hidden_obj.components.ppu.debug_layers.hidden_objs = 1;
+    for _ in 0..3 {
+        shown.run_frame();
+        hidden_bg.run_frame();
+        hidden_obj.run_frame();
+    }
+
+    let fb = |gb: &khangboy_core::Gameboy| gb.components.ppu.framebuffer;
+    assert!(fb(&shown).iter().all(|&x| x == 3));
+    // Only the object is left, and it's drawn with OBP0
+    assert_eq!(fb(&hidden_bg)[0], 3);
+    assert_eq!(fb(&hidden_bg)[8], 0);
+    assert_eq!(
+        hidden_bg.components.ppu.framebuffer_palettes[0],
+        palette::OBP0
+    );
+    assert!(fb(&hidden_obj).iter().all(|&x| x == 3));
+    assert_eq!(
+        hidden_obj.components.ppu.framebuffer_palettes[0],
+        palette::BGP
+    );
+
+    assert_eq!(hidden_bg.machine_state_hash(), shown.machine_state_hash());
+    assert_eq!(hidden_obj.machine_state_hash(), shown.machine_state_hash());
+}
+
+// Draws something that moves, with an OAM DMA transfer every frame
+// LD A,$93; LDH (LCDC),A; loop: LDH A,(LY); CP 144; JR NZ,loop; LDH A,(SCX); INC A; LDH (SCX),A;
+// LD A,$C0; LDH (DMA),A; wait: LDH A,(LY); CP 144; JR Z,wait; JR loop
+const MOVING: &[u8] = &[
+    0x3E, 0x93, 0xE0, 0x40, 0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0xF0, 0x43, 0x3C, 0xE0, 0x43, 0x3E,
+    0xC0, 0xE0, 0x46, 0xF0, 0x44, 0xFE, 0x90, 0x28, 0xFA, 0x18, 0xE9,
+];
+
+fn moving() -> Gameboy {
+    let mut gb = synthetic_rom(MOVING);
+    for addr in 0x8000..0x8010 {
+        gb.components.write_passive(addr, addr as u8 & 0x0F);
+    }
+    gb
+}
+
+#[test]
+fn loading_goes_back_exactly() {
+    let mut gb = moving();
+    gb.run(12345);
+    let state = gb.save_state();
+
+    gb.run_frame();
+    gb.run(5432);
+    let framebuffer = gb.components.ppu.framebuffer;
+    let later = gb.save_state();
+
+    gb.run_frame();
+    gb.load_state(&state).unwrap();
+    assert_eq!(gb.save_state(), state);
+    gb.run_frame();
+    gb.run(5432);
+    assert_eq!(gb.components.ppu.framebuffer, framebuffer);
+    assert_eq!(gb.save_state(), later);
+}
+
+#[test]
+fn rejects_other_states() {
+    let mut gb = moving();
+    gb.run_frame();
+    let state = gb.save_state();
+    gb.run_frame();
+    let current = gb.save_state();
+
+    let mut check = |data: &[u8], error: &str| {
+        assert_eq!(gb.load_state(data), Err(error.to_string()));
+        // Nothing changes when it fails
+        assert_eq!(gb.save_state(), current);
+    };
+
+    let mut other = synthetic_rom(&[0x18, 0xFE]);
+    other.run_frame();
+    check(&other.save_state(), "Save state is for a different ROM");
+
+    let mut bad = state.clone();
+    bad[0] = b'X';
+    check(&bad, "Not a save state");
+    check(b"KB", "Not a save state");
+
+    let mut bad = state.clone();
+    bad[4] = bad[4].wrapping_add(1);
+    let version = u32::from_le_bytes(bad[4..8].try_into().unwrap());
+    check(&bad, &format!("Unsupported save state version {version}"));
+
+    check(&state[..state.len() - 1], "State is truncated");
+    check(&state[..state.len() / 2], "State is truncated");
+    check(&state[..10], "State is truncated");
+    let mut bad = state.clone();
+    bad.push(0);
+    check(&bad, "Save state has extra data at the end");
+}
+
+#[test]
+fn rejects_out_of_range_values() {
+    let mut gb = moving();
+    gb.run_frame();
+    gb.run(1000);
+    let state = gb.save_state();
+
+    // The OAM DMA position is public, so its place in the state can be found directly
+    gb.components.ppu.oam_dma_idx ^= 0xFF;
+    let changed = gb.save_state();
+    gb.load_state(&state).unwrap();
+    let offset = (0..state.len()).find(|&i| state[i] != changed[i]).unwrap();
+    let mut bad = state.clone();
+    bad[offset] = 161;
+    assert_eq!(
+        gb.load_state(&bad),
+        Err("Invalid OAM DMA position".to_string())
+    );
+
+    // The rest of the PPU's registers come just before it, so break every byte there in turn
+    // Each one has to either load or fail cleanly without changing anything
+    let mut errors = HashSet::new();
+    for i in offset - 160..offset + 8 {
+        let mut bad = state.clone();
+        bad[i] = 0xFF;
+        match gb.load_state(&bad) {
+            Ok(()) => gb.load_state(&state).unwrap(),
+            Err(e) => {
+                assert_eq!(gb.save_state(), state, "{e} at {i}");
+                errors.insert(e);
+            }
+        }
+    }
+    for error in [
+        "Invalid PPU object count",
+        "Invalid LCD position",
+        "Invalid OAM DMA position",
+        "Invalid pixel FIFO",
+        "Invalid pixel fetcher state",
+        "Invalid PPU mode",
+    ] {
+        assert!(errors.contains(error), "{error} in {errors:?}");
+    }
+}
@@ -0,0 +1,338 @@
+// Runs the SM83 single-step tests (https://github.com/SingleStepTests/sm83) against the CPU
+// Each test gives the initial and final CPU/RAM state along with every M-cycle's bus activity
+// The JSON files are expected to be in khangboy-core/test_roms/sm83/v1 (or under KHANGBOY_TEST_ROMS)
+// and are skipped if they can't be found, but a few hand-written cases in the same format always
+// run from tests/sm83
+
+use khangboy_core::{bus::Bus, cpu::CPU};
+use serde::Deserialize;
+use std::{
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    final_state: State,
+    cycles: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    ime: u8,
+    ie: Option<u8>,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cycle {
+    Internal,
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+impl Cycle {
+    // Cycles are either null or [addr, data, pins], where pins is something like "r-m" or "-wm"
+    fn from_json(val: &serde_json::Value) -> Self {
+        let (addr, data, pins) = match val.as_array() {
+            Some(x) => (x[0].as_u64(), x[1].as_u64(), x[2].as_str().unwrap_or("---")),
+            None => return Cycle::Internal,
+        };
+        match (addr, data, pins.as_bytes()) {
+            (Some(addr), Some(data), [b'r', ..]) => Cycle::Read(addr as u16, data as u8),
+            (Some(addr), Some(data), [_, b'w', ..]) => Cycle::Write(addr as u16, data as u8),
+            _ => Cycle::Internal,
+        }
+    }
+}
+
+// A flat 64 KiB address space that records what happens on every M-cycle
+struct TestBus {
+    memory: Box<[u8; 0x10000]>,
+    trace: Vec<Cycle>,
+}
+
+impl TestBus {
+    fn new() -> Self {
+        Self {
+            memory: Box::new([0; 0x10000]),
+            trace: Vec::new(),
+        }
+    }
+}
+
+impl Bus for TestBus {
+    fn tick(&mut self) {
+        self.trace.push(Cycle::Internal);
+    }
+
+    fn read_passive(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write_passive(&mut self, addr: u16, val: u8) {
+        self.memory[addr as usize] = val;
+    }
+
+    fn cycle(&self) -> u64 {
+        self.trace.len() as u64
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.memory[0xFFFF] & self.memory[0xFF0F] & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.memory[0xFF0F] &= !(1 << bit);
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.memory[addr as usize];
+        self.trace.push(Cycle::Read(addr, val));
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.memory[addr as usize] = val;
+        self.trace.push(Cycle::Write(addr, val));
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    let test_roms = match std::env::var_os("KHANGBOY_TEST_ROMS") {
+        Some(x) => x.into(),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms"),
+    };
+    test_roms.join("sm83").join("v1")
+}
+
+// Returns a description of the first mismatch, if there is one
+fn run_case(case: &TestCase) -> Option<String> {
+    let mut bus = TestBus::new();
+    for &(addr, val) in &case.initial.ram {
+        bus.memory[addr as usize] = val;
+    }
+    if let Some(ie) = case.initial.ie {
+        bus.memory[0xFFFF] = ie;
+    }
+
+    let init = &case.initial;
+    let mut cpu = CPU::new();
+    cpu.a = init.a;
+    cpu.b = init.b;
+    cpu.c = init.c;
+    cpu.d = init.d;
+    cpu.e = init.e;
+    cpu.f = init.f;
+    cpu.h = init.h;
+    cpu.l = init.l;
+    cpu.sp = init.sp;
+    cpu.pc = init.pc;
+
+    // The CPU fetches the next opcode at the end of the previous instruction,
+    // but the tests start with the opcode fetch instead
+    // A fresh CPU starts with a NOP, so stepping once just fetches the opcode being tested
+    cpu.step(&mut bus);
+    cpu.ime = init.ime != 0;
+    cpu.step(&mut bus);
+
+    // That means the fetch of the following opcode isn't part of the test either
+    let trace = &bus.trace[..bus.trace.len() - 1];
+    let pc = cpu.pc.wrapping_sub(1);
+
+    let fin = &case.final_state;
+    let expected_regs = [fin.a, fin.b, fin.c, fin.d, fin.e, fin.f, fin.h, fin.l];
+    let actual_regs = [cpu.a, cpu.b, cpu.c, cpu.d, cpu.e, cpu.f, cpu.h, cpu.l];
+    if expected_regs != actual_regs {
+        return Some(format!(
+            "A/B/C/D/E/F/H/L: expected {expected_regs:02X?}, got {actual_regs:02X?}"
+        ));
+    }
+    if (fin.sp, fin.pc) != (cpu.sp, pc) {
+        return Some(format!(
+            "SP/PC: expected {:04X}/{:04X}, got {:04X}/{:04X}",
+            fin.sp, fin.pc, cpu.sp, pc
+        ));
+    }
+    if (fin.ime != 0) != cpu.ime {
+        return Some(format!("IME: expected {}, got {}", fin.ime, cpu.ime as u8));
+    }
+    for &(addr, val) in &fin.ram {
+        let actual = bus.memory[addr as usize];
+        if actual != val {
+            return Some(format!(
+                "RAM[{addr:04X}]: expected {val:02X}, got {actual:02X}"
+            ));
+        }
+    }
+
+    let expected_trace: Vec<Cycle> = case.cycles.iter().map(Cycle::from_json).collect();
+    if expected_trace != trace {
+        return Some(format!(
+            "Bus activity: expected {expected_trace:04X?}, got {trace:04X?}"
+        ));
+    }
+
+    None
+}
+
+#[test]
+fn sm83_single_step() {
+    let dir = tests_dir();
+    if !dir.is_dir() {
+        eprintln!(
+            "Skipping SM83 single-step tests, {} doesn't exist",
+            dir.display()
+        );
+        return;
+    }
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "json"))
+        .collect();
+    files.sort();
+    run_files(&files);
+}
+
+#[test]
+fn sm83_hand_written() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sm83/hand_written.json");
+    run_files(&[path]);
+}
+
+// Runs every case in some JSON files, failing if any of them don't pass
+fn run_files(files: &[PathBuf]) {
+    // Only the first failure for each file gets reported so the output stays readable
+    let mut failures = Vec::new();
+    for path in files {
+        let cases: Vec<TestCase> =
+            serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path).unwrap()))
+                .unwrap();
+        let name = path.file_stem().unwrap().to_string_lossy();
+        let mut failed = 0;
+        let mut first_failure = None;
+        for case in &cases {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| run_case(case)))
+                .unwrap_or_else(|_| Some("CPU panicked".into()));
+            if let Some(msg) = result {
+                failed += 1;
+                first_failure.get_or_insert(format!("{}: {msg}", case.name));
+            }
+        }
+        if let Some(msg) = first_failure {
+            failures.push(format!("{name} ({failed}/{} failed) {msg}", cases.len()));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}/{} files failed:\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}
+
+// The single-step tests only run one instruction without interrupts, so these are checked here
+
+// Sets up a CPU at 0x100 with an interrupt ready to be dispatched
+fn interrupt_at(sp: u16, ie: u8, interrupts: u8) -> (CPU, TestBus) {
+    let mut bus = TestBus::new();
+    bus.memory[0xFFFF] = ie;
+    bus.memory[0xFF0F] = interrupts;
+    let mut cpu = CPU::new();
+    cpu.set_instruction_addr(&mut bus, 0x100);
+    cpu.sp = sp;
+    cpu.ime = true;
+    (cpu, bus)
+}
+
+#[test]
+fn interrupt_dispatch() {
+    // Timer and VBlank are requested, but only the timer is enabled
+    let (mut cpu, mut bus) = interrupt_at(0xD000, 0x04, 0x05);
+    // 5 M-cycles for the dispatch, then 1 for the NOP at the handler
+    assert_eq!(cpu.step(&mut bus), 6);
+    assert_eq!(
+        bus.trace[..5],
+        [
+            Cycle::Internal,
+            Cycle::Internal,
+            Cycle::Write(0xCFFF, 0x01),
+            Cycle::Write(0xCFFE, 0x00),
+            Cycle::Read(0x0050, 0x00),
+        ]
+    );
+    assert_eq!(cpu.sp, 0xCFFE);
+    assert_eq!(cpu.instruction_addr(), 0x0051);
+    assert!(!cpu.ime);
+    assert_eq!(bus.memory[0xFF0F], 0x01);
+}
+
+#[test]
+fn interrupt_dispatch_pushing_onto_ie() {
+    // The high byte of PC (0x01) replaces IE before the interrupt is picked, cancelling it
+    let (mut cpu, mut bus) = interrupt_at(0x0000, 0x04, 0x04);
+    cpu.step(&mut bus);
+    assert_eq!(bus.memory[0xFFFF], 0x01);
+    assert_eq!(cpu.instruction_addr(), 0x0001);
+    assert_eq!(bus.memory[0xFF0F], 0x04);
+
+    // Or switches to a different one
+    let (mut cpu, mut bus) = interrupt_at(0x0000, 0x04, 0x05);
+    cpu.step(&mut bus);
+    assert_eq!(cpu.instruction_addr(), 0x0041);
+    assert_eq!(bus.memory[0xFF0F], 0x04);
+
+    // The low byte is written after it's picked, so that doesn't change anything
+    let (mut cpu, mut bus) = interrupt_at(0x0001, 0x04, 0x04);
+    cpu.step(&mut bus);
+    assert_eq!(bus.memory[0xFFFF], 0x00);
+    assert_eq!(bus.memory[0x0000], 0x01);
+    assert_eq!(cpu.instruction_addr(), 0x0051);
+    assert_eq!(bus.memory[0xFF0F], 0x00);
+}
+
+#[test]
+fn halt_bug() {
+    // HALT; INC A; NOP
+    let mut bus = TestBus::new();
+    bus.memory[0x100..0x103].copy_from_slice(&[0x76, 0x3C, 0x00]);
+    bus.memory[0xFFFF] = 0x01;
+    bus.memory[0xFF0F] = 0x01;
+    let mut cpu = CPU::new();
+    cpu.set_instruction_addr(&mut bus, 0x100);
+
+    // With IME off and an interrupt already pending, HALT doesn't halt and the byte after it gets
+    // read twice
+    for _ in 0..3 {
+        cpu.step(&mut bus);
+    }
+    assert_eq!(cpu.a, 2);
+    assert_eq!(cpu.instruction_addr(), 0x102);
+    assert_eq!(
+        bus.trace,
+        [
+            Cycle::Read(0x101, 0x3C),
+            Cycle::Read(0x101, 0x3C),
+            Cycle::Read(0x102, 0x00),
+        ]
+    );
+    // Nothing gets dispatched
+    assert_eq!(bus.memory[0xFF0F], 0x01);
+}
@@ -0,0 +1,47 @@
+use khangboy_core::symbols::SymbolTable;
+
+const SYMBOLS: &str = "
+; File generated by rgblink
+00:0150 Main
+00:0150 Entry
+00:0200 Main.loop
+01:4000 BankOne
+02:4000 BankTwo
+02:4100 BankTwo.end
+00:C000 wBuffer
+01:D000 wOther
+00:FF80 hTemp
+";
+
+#[test]
+fn finds_every_alias() {
+    let symbols = SymbolTable::parse(SYMBOLS);
+    assert_eq!(symbols.find("Main"), Some((0, 0x150)));
+    assert_eq!(symbols.find("Entry"), Some((0, 0x150)));
+    assert_eq!(symbols.find("BankTwo.end"), Some((2, 0x4100)));
+    assert_eq!(symbols.find("Missing"), None);
+    // Only one name is used for the address itself
+    assert_eq!(symbols.get(0, 0x150), Some("Entry"));
+}
+
+#[test]
+fn resolves_within_region_and_bank() {
+    let symbols = SymbolTable::parse(SYMBOLS);
+    assert_eq!(symbols.resolve(0, 0x100), None);
+    assert_eq!(symbols.resolve(0, 0x1FF), Some(("Entry", 0xAF)));
+    assert_eq!(symbols.resolve(0, 0x3FFF), Some(("Main.loop", 0x3DFF)));
+
+    // Switchable ROM only sees labels from the current bank
+    assert_eq!(symbols.resolve(1, 0x4200), Some(("BankOne", 0x200)));
+    assert_eq!(symbols.resolve(2, 0x4200), Some(("BankTwo.end", 0x100)));
+    assert_eq!(symbols.resolve(3, 0x4200), None);
+
+    // WRAM has one bank on the DMG, whatever the assembler says
+    assert_eq!(symbols.resolve(5, 0xC010), Some(("wBuffer", 0x10)));
+    assert_eq!(symbols.resolve(0, 0xD001), Some(("wOther", 1)));
+    // But labels don't leak into other regions
+    assert_eq!(symbols.resolve(0, 0xE000), None);
+    assert_eq!(symbols.resolve(0, 0x8000), None);
+    assert_eq!(symbols.format(0, 0xFF81), "hTemp+0x1");
+    assert_eq!(symbols.format(0, 0xFF00), "00:FF00");
+}
@@ -0,0 +1,208 @@
+// Runs public test ROM suites against the emulator
+// The ROMs aren't redistributable, so they're expected to be extracted to khangboy-core/test_roms
+// (or wherever KHANGBOY_TEST_ROMS points to) like this:
+//   blargg/cpu_instrs/individual/*.gb
+//   blargg/instr_timing/instr_timing.gb
+//   blargg/mem_timing/individual/*.gb
+//   blargg/oam_bug/rom_singles/*.gb
+//   mooneye/acceptance/**/*.gb
+//   dmg-acid2/dmg-acid2.gb and dmg-acid2/reference-dmg.png
+// Suites that can't be found are skipped, but result detection is still checked with a few tiny
+// ROMs built here
+// Running these with --release is recommended since some take a while
+
+mod common;
+
+use common::synthetic_rom;
+use khangboy_core::{
+    rom::rom_from_bytes,
+    test_rom::{run_test_rom, run_until_breakpoint, TestResult},
+    Gameboy,
+};
+use std::{
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+};
+
+// M-cycles per second
+const CLOCK_SPEED: u64 = 4194304 / 4;
+
+fn test_roms_dir() -> PathBuf {
+    match std::env::var_os("KHANGBOY_TEST_ROMS") {
+        Some(x) => x.into(),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms"),
+    }
+}
+
+// Recursively collects every .gb file in a directory
+fn find_roms(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_roms(&path, out);
+        } else if path.extension().is_some_and(|x| x == "gb") {
+            out.push(path);
+        }
+    }
+}
+
+fn load_rom(path: &Path) -> Gameboy {
+    let rom = rom_from_bytes(&std::fs::read(path).unwrap()).unwrap();
+    Gameboy::new(rom)
+}
+
+// Mooneye's tests are suffixed with the models they pass on
+// e.g. "-dmgABCmgb" or "-GS" (G: DMG and MGB, S: SGB and SGB2)
+fn runs_on_dmg(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    match stem.rsplit_once('-') {
+        Some((_, models)) if models.chars().all(|x| x.is_ascii_uppercase()) => models.contains('G'),
+        Some((_, models)) => models.contains("dmgABC"),
+        None => true,
+    }
+}
+
+// Runs every ROM in a suite, failing if any of them don't pass
+fn run_suite(suite: &str, seconds: u64, filter: impl Fn(&Path) -> bool) {
+    let dir = test_roms_dir().join(suite);
+    if !dir.is_dir() {
+        eprintln!("Skipping {suite}, {} doesn't exist", dir.display());
+        return;
+    }
+
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.retain(|x| filter(x));
+    roms.sort();
+
+    let mut failures = Vec::new();
+    for path in &roms {
+        let mut gb = load_rom(path);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            run_test_rom(&mut gb, seconds * CLOCK_SPEED)
+        }));
+        let name = path.strip_prefix(&dir).unwrap().display();
+        match result {
+            Ok(TestResult::Passed) => (),
+            Ok(TestResult::Failed(msg)) => failures.push(format!("{name}: {msg}")),
+            Ok(TestResult::Timeout) => failures.push(format!("{name}: Timed out")),
+            Err(_) => failures.push(format!("{name}: Emulator panicked")),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}/{} tests in {suite} failed:\n{}",
+        failures.len(),
+        roms.len(),
+        failures.join("\n")
+    );
+}
+
+// Runs a ROM until it hits LD B,B, then compares the framebuffer against a reference image
+fn run_screenshot(rom: &str, reference: &str, seconds: u64) {
+    let rom_path = test_roms_dir().join(rom);
+    let reference_path = test_roms_dir().join(reference);
+    if !rom_path.is_file() || !reference_path.is_file() {
+        eprintln!("Skipping {rom}, {} doesn't exist", rom_path.display());
+        return;
+    }
+
+    let mut gb = load_rom(&rom_path);
+    assert!(
+        run_until_breakpoint(&mut gb, seconds * CLOCK_SPEED),
+        "{rom} never finished"
+    );
+
+    // The current frame might've started before the test finished drawing, so wait for the
+    // next complete one
+    gb.run_frame();
+    gb.run_frame();
+
+    let expected = load_reference(&reference_path);
+    let mismatched = expected
+        .iter()
+        .zip(gb.components.ppu.framebuffer.iter())
+        .filter(|(x, y)| x != y)
+        .count();
+    assert_eq!(mismatched, 0, "{mismatched} pixels in {rom} don't match");
+}
+
+// Converts a 160x144 grayscale reference image to shades
+fn load_reference(path: &Path) -> Vec<u8> {
+    let mut decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (160, 144));
+
+    // Every channel is the same, so only the first one matters
+    // 0xFF, 0xAA, 0x55, 0x00 map to shades 0 to 3
+    let channels = info.color_type.samples();
+    buf[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|x| ((0xFF - x[0] as u16 + 0x2A) / 0x55) as u8)
+        .collect()
+}
+
+#[test]
+fn detects_mooneye_pass() {
+    // LD B,3; LD C,5; LD D,8; LD E,13; LD H,21; LD L,34; LD B,B; JR -2
+    let mut gb = synthetic_rom(&[
+        0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE,
+    ]);
+    assert_eq!(run_test_rom(&mut gb, CLOCK_SPEED), TestResult::Passed);
+}
+
+#[test]
+fn detects_blargg_serial_failure() {
+    // Sends each character with LD A,c; LDH (SB),A; LD A,$81; LDH (SC),A
+    let mut code = Vec::new();
+    for &c in b"Failed\n" {
+        code.extend_from_slice(&[0x3E, c, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+    }
+    code.extend_from_slice(&[0x18, 0xFE]);
+    let mut gb = synthetic_rom(&code);
+    assert_eq!(
+        run_test_rom(&mut gb, CLOCK_SPEED),
+        TestResult::Failed("Failed".into())
+    );
+}
+
+#[test]
+fn times_out_without_a_result() {
+    // JR -2
+    let mut gb = synthetic_rom(&[0x18, 0xFE]);
+    assert_eq!(run_test_rom(&mut gb, 1000), TestResult::Timeout);
+}
+
+#[test]
+fn blargg_cpu_instrs() {
+    run_suite("blargg/cpu_instrs/individual", 30, |_| true);
+}
+
+#[test]
+fn blargg_instr_timing() {
+    run_suite("blargg/instr_timing", 10, |_| true);
+}
+
+#[test]
+fn blargg_mem_timing() {
+    run_suite("blargg/mem_timing/individual", 10, |_| true);
+}
+
+#[test]
+fn blargg_oam_bug() {
+    run_suite("blargg/oam_bug/rom_singles", 10, |_| true);
+}
+
+#[test]
+fn mooneye_acceptance() {
+    run_suite("mooneye/acceptance", 10, runs_on_dmg);
+}
+
+#[test]
+fn dmg_acid2() {
+    run_screenshot("dmg-acid2/dmg-acid2.gb", "dmg-acid2/reference-dmg.png", 10);
+}
@@ -0,0 +1,177 @@
+use khangboy_core::{
+    cdl::CodeDataLog,
+    disasm::trace_line,
+    profiler::Profiler,
+    symbols::SymbolTable,
+    test_rom::{run_test_rom_with, TestResult},
+    Gameboy,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+};
+
+// M-cycles per second
+const CLOCK_SPEED: u64 = 4194304 / 4;
+
+// Most test ROMs finish well within this, but cpu_instrs takes almost a minute
+const DEFAULT_SECONDS: u64 = 120;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Options can go anywhere, and everything else is positional
+    let mut cli = std::env::args();
+    let mut args: Vec<String> = cli.next().into_iter().collect();
+    let mut profile_path = None;
+    let mut cdl_path = None;
+    while let Some(arg) = cli.next() {
+        match arg.as_str() {
+            "--profile" => profile_path = Some(cli.next().ok_or("--profile needs a path")?),
+            "--cdl" => cdl_path = Some(cli.next().ok_or("--cdl needs a path")?),
+            _ => args.push(arg),
+        }
+    }
+    if !(2..=4).contains(&args.len()) {
+        println!(
+            "Usage: khangboy-headless [rom or directory] [max emulated seconds] [trace log] \
+             [--profile folded stacks] [--cdl code/data log]"
+        );
+        return Ok(());
+    }
+    let seconds = match args.get(2) {
+        Some(x) => x.parse()?,
+        None => DEFAULT_SECONDS,
+    };
+
+    let path = Path::new(&args[1]);
+    let roms = if path.is_dir() {
+        let mut roms = Vec::new();
+        find_roms(path, &mut roms)?;
+        roms.sort();
+        roms
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    // Every executed instruction gets logged, so this only makes sense for one ROM
+    let mut trace = match args.get(3) {
+        Some(_) if roms.len() != 1 => return Err("Trace logs only work with a single ROM".into()),
+        Some(x) => Some(BufWriter::new(File::create(x)?)),
+        None => None,
+    };
+    if profile_path.is_some() && roms.len() != 1 {
+        return Err("Profiling only works with a single ROM".into());
+    }
+    if cdl_path.is_some() && roms.len() != 1 {
+        return Err("Code/data logs only work with a single ROM".into());
+    }
+    // Each of these needs to step the CPU through its own bus
+    if profile_path.is_some() && cdl_path.is_some() {
+        return Err("--profile and --cdl can't be used at the same time".into());
+    }
+
+    let mut results = Vec::new();
+    for rom_path in &roms {
+        let result = run_rom(
+            rom_path,
+            seconds * CLOCK_SPEED,
+            trace.as_mut(),
+            profile_path.as_deref().map(Path::new),
+            cdl_path.as_deref().map(Path::new),
+        );
+        let name = rom_path.strip_prefix(path).unwrap_or(rom_path);
+        results.push((name.display().to_string(), result));
+    }
+
+    // Print a summary table
+    let width = results.iter().map(|(x, _)| x.len()).max().unwrap_or(0);
+    let mut passed = 0;
+    for (name, result) in &results {
+        let status = match result {
+            Ok(TestResult::Passed) => {
+                passed += 1;
+                "PASS".to_string()
+            }
+            Ok(TestResult::Failed(msg)) => format!("FAIL  {}", msg.replace('\n', " ")),
+            Ok(TestResult::Timeout) => "TIMEOUT".to_string(),
+            Err(err) => format!("ERROR {err}"),
+        };
+        println!("{name:width$}  {status}");
+    }
+    println!("{passed}/{} passed", results.len());
+
+    if passed != results.len() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Recursively collects every .gb file in a directory
+fn find_roms(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, out)?;
+        } else if path.extension().is_some_and(|x| x == "gb") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn run_rom(
+    path: &Path,
+    max_cycles: u64,
+    mut trace: Option<&mut BufWriter<File>>,
+    profile_path: Option<&Path>,
+    cdl_path: Option<&Path>,
+) -> Result<TestResult, String> {
+    let data = std::fs::read(path).map_err(|x| x.to_string())?;
+    let rom = khangboy_core::rom::rom_from_bytes(&data)?;
+    let mut gb = Gameboy::new(rom);
+
+    // Labels from a .sym file next to the ROM show up in the trace log
+    let symbols = SymbolTable::load(path.with_extension("sym")).unwrap_or_default();
+    let mut profiler = profile_path.map(|_| Profiler::new());
+
+    // Existing logs get added to, so coverage can be built up over multiple runs
+    let mut cdl = match cdl_path {
+        Some(cdl_path) if cdl_path.exists() => {
+            Some(CodeDataLog::load(&gb, cdl_path).map_err(|x| x.to_string())?)
+        }
+        Some(_) => Some(CodeDataLog::new(&gb)),
+        None => None,
+    };
+
+    // Unimplemented features panic, which shouldn't take down the rest of the run
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        run_test_rom_with(&mut gb, max_cycles, |gb| {
+            if let Some(trace) = &mut trace {
+                writeln!(trace, "{}", trace_line(gb, &symbols)).ok();
+            }
+            match (&mut profiler, &mut cdl) {
+                (Some(profiler), _) => profiler.step(gb),
+                (_, Some(cdl)) => cdl.step(gb),
+                _ => gb.cpu.step(&mut gb.components),
+            };
+        })
+    }))
+    .map_err(|_| "Emulator panicked".to_string());
+    if let Some(trace) = trace {
+        trace.flush().map_err(|x| x.to_string())?;
+    }
+    if let (Some(profiler), Some(profile_path)) = (profiler, profile_path) {
+        let mut out = BufWriter::new(File::create(profile_path).map_err(|x| x.to_string())?);
+        profiler
+            .write_folded(&mut out, &symbols)
+            .and_then(|_| out.flush())
+            .map_err(|x| x.to_string())?;
+    }
+    if let (Some(cdl), Some(cdl_path)) = (cdl, cdl_path) {
+        cdl.save(cdl_path).map_err(|x| x.to_string())?;
+        let (code, data) = cdl.coverage();
+        println!("Logged {code} bytes of code and {data} bytes of data");
+    }
+    result
+}
@@ -1,16 +1,50 @@
 use imgui_glow_renderer::glow::{self, HasContext};
-use khangboy_core::Gameboy;
+use khangboy_core::{
+    gb::FRAME_CYCLES,
+    gdb::GdbStub,
+    movie::{Movie, MoviePlayer, MovieRecorder, PlaybackStatus},
+    palette::{Palette, Palettes},
+    ppu::DebugLayers,
+    rewind::Rewind,
+    runahead::RunAhead,
+    symbols::SymbolTable,
+    Gameboy,
+};
 use sdl2::{
     audio::{AudioCallback, AudioSpec, AudioSpecDesired},
     event::Event,
-    keyboard::Scancode,
+    keyboard::{Mod, Scancode},
+};
+use std::{
+    fmt::Display,
+    net::TcpListener,
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
-use std::{fmt::Display, sync::mpsc, thread, time::Instant};
+
+mod slots;
 
 enum EmuThreadCommand {
     Quit,
     KeyDown(usize),
     KeyUp(usize),
+    SaveState(usize),
+    LoadState(usize),
+    Rewind(bool),
+    RecordMovie,
+    PlayMovie,
+    StopMovie,
+    SetRunAhead(u32),
+    SetDebugLayers(DebugLayers),
+}
+
+// Movies are recorded from power-on, so starting one resets the Gameboy
+enum MovieMode {
+    None,
+    Recording(MovieRecorder),
+    Playing(MoviePlayer),
 }
 
 // TODO: Syncing this stuff shouldn't happen if the windows are visible
@@ -22,7 +56,13 @@ struct SharedData {
     tile_data_hash: u64,
 
     fb: Box<[u8; 160 * 144]>,
+    fb_palettes: Box<[u8; 160 * 144]>,
     fb_hash: u64,
+
+    // Incremented whenever a save state slot is written so the slot browser knows to refresh
+    slots_generation: u64,
+
+    movie_status: String,
 }
 
 impl Default for SharedData {
@@ -32,7 +72,10 @@ impl Default for SharedData {
             tile_data: Box::new([0; 0x1800]),
             tile_data_hash: 0,
             fb: Box::new([0; 160 * 144]),
+            fb_palettes: Box::new([0; 160 * 144]),
             fb_hash: 0,
+            slots_generation: 0,
+            movie_status: String::new(),
         }
     }
 }
@@ -76,22 +119,59 @@ impl Display for CPURegisters {
 
 fn emu_thread(
     rom_path: String,
+    gdb_listener: Option<TcpListener>,
     audio_spec: AudioSpec,
     mut audio_input: triple_buffer::Input<Vec<i16>>,
     mut buf_input: triple_buffer::Input<SharedData>,
     rx: mpsc::Receiver<EmuThreadCommand>,
 ) {
-    let rom = khangboy_core::rom::rom_from_bytes(&std::fs::read(rom_path).unwrap()).unwrap();
-    let mut gb = Gameboy::new(rom);
+    let rom_data = std::fs::read(&rom_path).unwrap();
+    let power_on = || Gameboy::new(khangboy_core::rom::rom_from_bytes(&rom_data).unwrap());
+    let mut gb = power_on();
+
+    // Wait for GDB to attach before running anything if requested
+    // Labels from a .sym file next to the ROM can be used in monitor commands
+    let mut gdb = gdb_listener.and_then(|listener| {
+        let gdb = listener
+            .accept()
+            .and_then(|(stream, _)| GdbStub::from_stream(stream));
+        match gdb {
+            Ok(mut gdb) => {
+                let sym_path = Path::new(&rom_path).with_extension("sym");
+                gdb.set_symbols(SymbolTable::load(sym_path).unwrap_or_default());
+                Some(gdb)
+            }
+            Err(e) => {
+                println!("Failed to connect to GDB: {e}");
+                None
+            }
+        }
+    });
 
-    // ~2ms per timestep
     const CLOCK_SPEED: u64 = 4194304 / 4;
-    const TARGET_CYCLES: u64 = CLOCK_SPEED / 512;
 
     let start = Instant::now();
     let mut cycles_executed = 0;
     let mut key_state = 0x00;
     let mut sample = 0;
+    let mut slots_generation = 0;
+
+    // Snapshots every 2 frames for up to 20 seconds
+    // Holding the rewind key steps back at the same rate, so it plays at normal speed
+    const REWIND_INTERVAL: u64 = 2;
+    const REWIND_STEP_TIME: Duration = Duration::from_micros(16743 * REWIND_INTERVAL);
+    let mut rewind = Rewind::new(REWIND_INTERVAL, 600);
+    let mut rewinding = false;
+    let mut last_rewind_step = Instant::now();
+
+    // Disabled by default since it multiplies how much has to be emulated
+    let mut run_ahead = RunAhead::new(0);
+
+    // Kept here since the Gameboy gets replaced when movies start
+    let mut debug_layers = DebugLayers::default();
+
+    let movie_path = Path::new(&rom_path).with_extension("kbm");
+    let mut movie = MovieMode::None;
     loop {
         // Handle any messages from the main thread
         if let Ok(msg) = rx.try_recv() {
@@ -99,28 +179,139 @@ fn emu_thread(
                 EmuThreadCommand::Quit => break,
                 EmuThreadCommand::KeyDown(bit) => key_state |= 1 << bit,
                 EmuThreadCommand::KeyUp(bit) => key_state &= !(1 << bit),
+                EmuThreadCommand::SaveState(slot) => {
+                    match slots::save_slot(&gb, &rom_path, slot) {
+                        Ok(()) => println!("Saved state to slot {slot}"),
+                        Err(e) => println!("Failed to save state to slot {slot}: {e}"),
+                    }
+                    slots_generation += 1;
+                }
+                // Loading states and rewinding would make a movie useless, so they're disabled
+                // while one is active
+                EmuThreadCommand::LoadState(_) if !matches!(movie, MovieMode::None) => {
+                    println!("Can't load states while a movie is active")
+                }
+                EmuThreadCommand::LoadState(slot) => {
+                    match slots::load_slot(&mut gb, &rom_path, slot) {
+                        Ok(()) => {
+                            // Rewinding shouldn't go back to before the state was loaded
+                            rewind.clear();
+                            println!("Loaded state from slot {slot}");
+                        }
+                        Err(e) => println!("Failed to load state from slot {slot}: {e}"),
+                    }
+                }
+                EmuThreadCommand::Rewind(x) => rewinding = x,
+                EmuThreadCommand::RecordMovie => {
+                    gb = power_on();
+                    rewind.clear();
+                    movie = MovieMode::Recording(MovieRecorder::new(&gb).unwrap());
+                    println!("Recording movie to {}", movie_path.display());
+                }
+                EmuThreadCommand::PlayMovie => {
+                    let player = Movie::load(&movie_path).and_then(|x| {
+                        gb = power_on();
+                        MoviePlayer::new(x, &mut gb)
+                    });
+                    match player {
+                        Ok(player) => {
+                            rewind.clear();
+                            movie = MovieMode::Playing(player);
+                            println!("Playing movie from {}", movie_path.display());
+                        }
+                        Err(e) => println!("Failed to play movie: {e}"),
+                    }
+                }
+                EmuThreadCommand::StopMovie => {
+                    if let MovieMode::Recording(recorder) =
+                        std::mem::replace(&mut movie, MovieMode::None)
+                    {
+                        let frames = recorder.frames();
+                        match recorder.finish().save(&movie_path) {
+                            Ok(()) => println!("Saved {frames} frame movie"),
+                            Err(e) => println!("Failed to save movie: {e}"),
+                        }
+                    }
+                }
+                EmuThreadCommand::SetRunAhead(frames) => run_ahead.frames = frames,
+                EmuThreadCommand::SetDebugLayers(layers) => debug_layers = layers,
             }
         }
 
-        // Run the emulator
+        // Run the emulator a frame at a time, waiting until real time has caught up
         // TODO: This method of throttling kinda sucks
-        let cycles_to_run = loop {
-            let diff = (Instant::now().duration_since(start).as_secs_f64() * CLOCK_SPEED as f64)
-                as u64
-                - cycles_executed;
-            if diff >= TARGET_CYCLES {
-                break diff;
+        // Emulation can get ahead of real time (e.g. after a frame that took longer than usual),
+        // so this compares instead of subtracting
+        loop {
+            let elapsed =
+                (Instant::now().duration_since(start).as_secs_f64() * CLOCK_SPEED as f64) as u64;
+            if elapsed >= cycles_executed + FRAME_CYCLES {
+                break;
             }
-        };
+        }
         gb.components.joypad.cur_input = key_state;
-        cycles_executed += gb.run(cycles_to_run);
+        gb.components.ppu.debug_layers = debug_layers;
+        cycles_executed += match &mut movie {
+            // Movies bypass the debugger
+            MovieMode::Recording(recorder) => {
+                let info = recorder.run_frame(&mut gb, key_state);
+                update_run_ahead(&mut run_ahead, &mut gb);
+                info.cycles
+            }
+            MovieMode::Playing(player) => {
+                let start_cycle = gb.components.cycle;
+                let status = player.run_frame(&mut gb);
+                if status != PlaybackStatus::Playing {
+                    match status {
+                        PlaybackStatus::Desynced(frame) => {
+                            println!("Movie desynced at frame {frame}")
+                        }
+                        _ => println!("Movie finished"),
+                    }
+                    movie = MovieMode::None;
+                }
+                update_run_ahead(&mut run_ahead, &mut gb);
+                (gb.components.cycle - start_cycle).max(FRAME_CYCLES)
+            }
+            MovieMode::None if rewinding => {
+                // Time spent rewinding still counts so the emulator doesn't try to catch up
+                if last_rewind_step.elapsed() >= REWIND_STEP_TIME {
+                    rewind.step_back(&mut gb);
+                    update_run_ahead(&mut run_ahead, &mut gb);
+                    last_rewind_step = Instant::now();
+                }
+                FRAME_CYCLES
+            }
+            MovieMode::None => {
+                let executed = match &mut gdb {
+                    // Same goes for time spent stopped in the debugger
+                    // Running ahead would hide where execution actually is, so it's skipped
+                    Some(gdb) if gdb.attached() => {
+                        let executed = gdb.run_frame(&mut gb).max(FRAME_CYCLES);
+                        run_ahead.copy_framebuffer(&gb);
+                        executed
+                    }
+                    _ => {
+                        let info = gb.run_frame();
+                        update_run_ahead(&mut run_ahead, &mut gb);
+                        info.cycles
+                    }
+                };
+                rewind.record(&gb);
+                executed
+            }
+        };
 
         // Update the shared data
         {
             let tile_data = &gb.components.ppu.vram[..0x1800];
             let tile_data_hash = xxhash_rust::xxh3::xxh3_64(tile_data);
-            let fb = &gb.components.ppu.framebuffer;
-            let fb_hash = xxhash_rust::xxh3::xxh3_64(fb);
+            let fb = &run_ahead.framebuffer[..];
+            let fb_palettes = &run_ahead.framebuffer_palettes[..];
+            let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+            hasher.update(fb);
+            hasher.update(fb_palettes);
+            let fb_hash = hasher.digest();
             let input = buf_input.input_buffer();
             input.registers.update(&gb.cpu);
             if input.tile_data_hash != tile_data_hash {
@@ -129,8 +320,17 @@ fn emu_thread(
             }
             if input.fb_hash != fb_hash {
                 input.fb.clone_from_slice(fb);
+                input.fb_palettes.clone_from_slice(fb_palettes);
                 input.fb_hash = fb_hash;
             }
+            input.slots_generation = slots_generation;
+            input.movie_status = match &movie {
+                MovieMode::None => "Stopped".into(),
+                MovieMode::Recording(x) => format!("Recording frame {}", x.frames()),
+                MovieMode::Playing(x) => {
+                    format!("Playing frame {}/{}", x.frame(), x.movie().frames())
+                }
+            };
         }
         buf_input.publish();
 
@@ -172,17 +372,37 @@ impl AudioCallback for PlaybackCallback {
                 *dst = 0;
             }
         } else {
-            data.clone_from_slice(&audio);
+            data.clone_from_slice(audio);
         }
     }
 }
 
+fn update_run_ahead(run_ahead: &mut RunAhead, gb: &mut Gameboy) {
+    if let Err(e) = run_ahead.update(gb) {
+        println!("Failed to go back after running ahead: {e}");
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: khangboy-sdl2 [rom]");
+    if args.len() != 2 && args.len() != 3 {
+        println!("Usage: khangboy-sdl2 [rom] [gdb port]");
         return Ok(());
     }
+    let gdb_port: Option<u16> = match args.get(2) {
+        Some(x) => Some(x.parse()?),
+        None => None,
+    };
+    // Bound here so a port that's already in use is reported before anything starts
+    let gdb_listener = match gdb_port {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .map_err(|e| format!("Couldn't listen for GDB on port {port}: {e}"))?;
+            println!("Waiting for GDB to connect on port {port}");
+            Some(listener)
+        }
+        None => None,
+    };
 
     // Initialize SDL2
     let sdl = sdl2::init()?;
@@ -243,75 +463,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut renderer = imgui_glow_renderer::AutoRenderer::initialize(gl, &mut imgui)?;
 
     // Allocate tile data viewer texture
-    let tile_tex = unsafe {
-        let ctx = renderer.gl_context();
-        let tex = ctx.create_texture()?;
-        ctx.bind_texture(glow::TEXTURE_2D, Some(tex));
-        ctx.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_MIN_FILTER,
-            glow::NEAREST as _,
-        );
-        ctx.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_MAG_FILTER,
-            glow::NEAREST as _,
-        );
-        ctx.tex_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            glow::RGB as i32,
-            16 * 8,
-            24 * 8,
-            0,
-            glow::RGB,
-            glow::UNSIGNED_BYTE,
-            None,
-        );
-        tex
-    };
+    let tile_tex = create_texture(&renderer, 16 * 8, 24 * 8)?;
 
     // Allocate framebuffer texture
-    let fb_tex = unsafe {
-        let ctx = renderer.gl_context();
-        let tex = ctx.create_texture()?;
-        ctx.bind_texture(glow::TEXTURE_2D, Some(tex));
-        ctx.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_MIN_FILTER,
-            glow::NEAREST as _,
-        );
-        ctx.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_MAG_FILTER,
-            glow::NEAREST as _,
-        );
-        ctx.tex_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            glow::RGB as i32,
-            160,
-            144,
-            0,
-            glow::RGB,
-            glow::UNSIGNED_BYTE,
-            None,
-        );
-        tex
-    };
+    let fb_tex = create_texture(&renderer, 160, 144)?;
+
+    // Allocate save state slot thumbnail textures
+    let slot_texs = (0..slots::SLOT_COUNT)
+        .map(|_| create_texture(&renderer, 160, 144))
+        .collect::<Result<Vec<_>, _>>()?;
 
     // Spawn the emulation thread
     let (tx, rx) = mpsc::channel();
     let (buf_input, mut buf_output) = triple_buffer::triple_buffer(&Default::default());
     let rom_path = args[1].clone();
-    thread::spawn(move || emu_thread(rom_path, audio_spec.unwrap(), audio_input, buf_input, rx));
+    let emu_rom_path = rom_path.clone();
+    thread::spawn(move || {
+        emu_thread(
+            emu_rom_path,
+            gdb_listener,
+            audio_spec.unwrap(),
+            audio_input,
+            buf_input,
+            rx,
+        )
+    });
 
     // Run main event processing loop
     let mut event_pump = sdl.event_pump()?;
-    let mut tile_data_temp = Box::new([0; (16 * 8) * (24 * 8) * 3]);
+    let mut tile_data_temp = Box::new([0; (16 * 8) * (24 * 8) * 4]);
     let mut tile_data_hash = 0;
-    let mut fb_temp = Box::new([0; 160 * 144 * 3]);
+    let mut fb_temp = Box::new([0; 160 * 144 * 4]);
     let mut fb_hash = 0;
+    let mut slot_infos = Vec::new();
+    let mut slots_generation = None;
+    let mut run_ahead_frames = 0u32;
+    let mut palettes = Palettes::default();
+    let mut separate_palettes = false;
+    let mut debug_layers = DebugLayers::default();
     'main: loop {
         for event in event_pump.poll_iter() {
             // TODO: This should be configurable
@@ -325,6 +514,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Scancode::Up,
                 Scancode::Down,
             ];
+            const REWIND_KEY: Scancode = Scancode::Backspace;
+            // F1-F10 load from slots 1-10, and holding shift saves instead
+            const SLOT_KEYS: [Scancode; slots::SLOT_COUNT] = [
+                Scancode::F1,
+                Scancode::F2,
+                Scancode::F3,
+                Scancode::F4,
+                Scancode::F5,
+                Scancode::F6,
+                Scancode::F7,
+                Scancode::F8,
+                Scancode::F9,
+                Scancode::F10,
+            ];
             platform.handle_event(&mut imgui, &event);
 
             match event {
@@ -333,13 +536,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     timestamp: _,
                     window_id: _,
                     keycode: _,
-                    scancode,
-                    keymod: _,
+                    scancode: Some(scancode),
+                    keymod,
                     repeat: false,
                 } => {
-                    if let Some(scancode) = scancode {
-                        if let Some(bit) = KEYBINDS.iter().position(|&x| x == scancode) {
-                            tx.send(EmuThreadCommand::KeyDown(bit))?;
+                    if let Some(bit) = KEYBINDS.iter().position(|&x| x == scancode) {
+                        tx.send(EmuThreadCommand::KeyDown(bit))?;
+                    }
+                    if scancode == REWIND_KEY {
+                        tx.send(EmuThreadCommand::Rewind(true))?;
+                    }
+                    if let Some(idx) = SLOT_KEYS.iter().position(|&x| x == scancode) {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            tx.send(EmuThreadCommand::SaveState(idx + 1))?;
+                        } else {
+                            tx.send(EmuThreadCommand::LoadState(idx + 1))?;
                         }
                     }
                 }
@@ -347,14 +558,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     timestamp: _,
                     window_id: _,
                     keycode: _,
-                    scancode,
+                    scancode: Some(scancode),
                     keymod: _,
                     repeat: false,
                 } => {
-                    if let Some(scancode) = scancode {
-                        if let Some(bit) = KEYBINDS.iter().position(|&x| x == scancode) {
-                            tx.send(EmuThreadCommand::KeyUp(bit))?;
-                        }
+                    if let Some(bit) = KEYBINDS.iter().position(|&x| x == scancode) {
+                        tx.send(EmuThreadCommand::KeyUp(bit))?;
+                    }
+                    if scancode == REWIND_KEY {
+                        tx.send(EmuThreadCommand::Rewind(false))?;
                     }
                 }
                 _ => (),
@@ -371,7 +583,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         if output.tile_data_hash != tile_data_hash {
             // TODO: The math here is horrible
-            const COLORS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
             for y in 0..24 {
                 for x in 0..16 {
                     for ty in 0..8 {
@@ -382,62 +593,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             let hi = (output.tile_data[(y * 128 + x * 8 + ty) * 2 + 1] >> (7 - tx))
                                 as usize
                                 & 1;
-                            let color = COLORS[(hi << 1) | lo];
-                            tile_data_temp[((y * 8 + ty) * (16 * 8) + (x * 8 + tx)) * 3] = color;
-                            tile_data_temp[((y * 8 + ty) * (16 * 8) + (x * 8 + tx)) * 3 + 1] =
-                                color;
-                            tile_data_temp[((y * 8 + ty) * (16 * 8) + (x * 8 + tx)) * 3 + 2] =
-                                color;
+                            let color = palettes.bg.color(((hi << 1) | lo) as u8);
+                            let idx = ((y * 8 + ty) * (16 * 8) + (x * 8 + tx)) * 4;
+                            tile_data_temp[idx..idx + 4].copy_from_slice(&color);
                         }
                     }
                 }
             }
-            unsafe {
-                renderer
-                    .gl_context()
-                    .bind_texture(glow::TEXTURE_2D, Some(tile_tex));
-                renderer.gl_context().tex_sub_image_2d(
-                    glow::TEXTURE_2D,
-                    0,
-                    0,
-                    0,
-                    16 * 8,
-                    24 * 8,
-                    glow::RGB,
-                    glow::UNSIGNED_BYTE,
-                    glow::PixelUnpackData::Slice(tile_data_temp.as_ref()),
-                );
-            }
+            upload_texture(&renderer, tile_tex, 16 * 8, 24 * 8, tile_data_temp.as_ref());
             tile_data_hash = output.tile_data_hash;
         }
 
         if output.fb_hash != fb_hash {
-            const COLORS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
-            for i in 0..(160 * 144) {
-                let col = COLORS[output.fb[i] as usize & 3];
-                fb_temp[i * 3] = col;
-                fb_temp[i * 3 + 1] = col;
-                fb_temp[i * 3 + 2] = col;
-            }
-            unsafe {
-                renderer
-                    .gl_context()
-                    .bind_texture(glow::TEXTURE_2D, Some(fb_tex));
-                renderer.gl_context().tex_sub_image_2d(
-                    glow::TEXTURE_2D,
-                    0,
-                    0,
-                    0,
-                    160,
-                    144,
-                    glow::RGB,
-                    glow::UNSIGNED_BYTE,
-                    glow::PixelUnpackData::Slice(fb_temp.as_ref()),
-                );
-            }
+            palettes.framebuffer_to_rgba(
+                output.fb.as_ref(),
+                output.fb_palettes.as_ref(),
+                fb_temp.as_mut(),
+            );
+            upload_texture(&renderer, fb_tex, 160, 144, fb_temp.as_ref());
             fb_hash = output.fb_hash;
         }
 
+        // Reread the slots whenever one gets saved
+        if slots_generation != Some(output.slots_generation) {
+            slot_infos = (1..=slots::SLOT_COUNT)
+                .map(|x| slots::read_slot_info(&rom_path, x))
+                .collect();
+            for (info, &tex) in slot_infos.iter().zip(slot_texs.iter()) {
+                if let Some(info) = info {
+                    // Thumbnails only have shades, so they're drawn entirely with the BG palette
+                    palettes
+                        .bg
+                        .shades_to_rgba(info.thumbnail.as_ref(), fb_temp.as_mut());
+                    upload_texture(&renderer, tex, 160, 144, fb_temp.as_ref());
+                }
+            }
+            slots_generation = Some(output.slots_generation);
+        }
+
         ui.window("Registers")
             .size([90.0, 180.0], imgui::Condition::FirstUseEver)
             .position([75.0, 100.0], imgui::Condition::FirstUseEver)
@@ -446,7 +639,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             });
         ui.window("Framebuffer")
             .size(
-                [160.0 * 2.0 + 16.0, 144.0 * 2.0 + 36.0],
+                [160.0 * 2.0 + 16.0, 144.0 * 2.0 + 84.0],
                 imgui::Condition::FirstUseEver,
             )
             .position([180.0, 100.0], imgui::Condition::FirstUseEver)
@@ -456,6 +649,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     [160.0 * 2.0, 144.0 * 2.0],
                 )
                 .build(ui);
+
+                let mut changed = ui.checkbox("Hide BG", &mut debug_layers.hide_bg);
+                ui.same_line();
+                changed |= ui.checkbox("Hide window", &mut debug_layers.hide_window);
+                ui.same_line();
+                changed |= ui.checkbox("Hide objects", &mut debug_layers.hide_objs);
+                if ui.collapsing_header("Hide objects by OAM index", imgui::TreeNodeFlags::empty())
+                {
+                    for i in 0..40 {
+                        let mut hidden = debug_layers.hidden_objs & (1 << i) != 0;
+                        if i % 8 != 0 {
+                            ui.same_line();
+                        }
+                        if ui.checkbox(format!("{i:02}"), &mut hidden) {
+                            debug_layers.hidden_objs ^= 1 << i;
+                            changed = true;
+                        }
+                    }
+                }
+                if changed {
+                    tx.send(EmuThreadCommand::SetDebugLayers(debug_layers)).ok();
+                }
             });
         ui.window("PPU Tile Data")
             .size(
@@ -471,6 +686,86 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .build(ui);
             });
 
+        ui.window("Movie")
+            .size([220.0, 80.0], imgui::Condition::FirstUseEver)
+            .position([850.0, 600.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.text(&output.movie_status);
+                if ui.button("Record") {
+                    tx.send(EmuThreadCommand::RecordMovie).ok();
+                }
+                ui.same_line();
+                if ui.button("Play") {
+                    tx.send(EmuThreadCommand::PlayMovie).ok();
+                }
+                ui.same_line();
+                if ui.button("Stop") {
+                    tx.send(EmuThreadCommand::StopMovie).ok();
+                }
+            });
+        ui.window("Run-Ahead")
+            .size([220.0, 60.0], imgui::Condition::FirstUseEver)
+            .position([850.0, 700.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if ui.slider("Frames", 0, 4, &mut run_ahead_frames) {
+                    tx.send(EmuThreadCommand::SetRunAhead(run_ahead_frames))
+                        .ok();
+                }
+            });
+        ui.window("Palette")
+            .size([300.0, 380.0], imgui::Condition::FirstUseEver)
+            .position([1220.0, 100.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let mut changed = ui.checkbox("Separate object palettes", &mut separate_palettes);
+                changed |= edit_palette(ui, "BG", &mut palettes.bg);
+                if separate_palettes {
+                    changed |= edit_palette(ui, "OBP0", &mut palettes.obp0);
+                    changed |= edit_palette(ui, "OBP1", &mut palettes.obp1);
+                } else {
+                    palettes = Palettes::uniform(palettes.bg);
+                }
+                // Redraw everything with the new colors
+                if changed {
+                    tile_data_hash = 0;
+                    fb_hash = 0;
+                    slots_generation = None;
+                }
+            });
+        ui.window("Save States")
+            .size([360.0, 480.0], imgui::Condition::FirstUseEver)
+            .position([850.0, 100.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                for (i, info) in slot_infos.iter().enumerate() {
+                    let slot = i + 1;
+                    if info.is_some() {
+                        imgui::Image::new(
+                            imgui::TextureId::new(slot_texs[i] as usize),
+                            [80.0, 72.0],
+                        )
+                        .build(ui);
+                    } else {
+                        ui.dummy([80.0, 72.0]);
+                    }
+                    ui.same_line();
+                    ui.group(|| {
+                        ui.text(format!("Slot {slot} (F{slot})"));
+                        match info {
+                            Some(info) => ui.text(slots::format_timestamp(info.timestamp)),
+                            None => ui.text_disabled("Empty"),
+                        }
+                        if ui.button(format!("Save##{slot}")) {
+                            tx.send(EmuThreadCommand::SaveState(slot)).ok();
+                        }
+                        if info.is_some() {
+                            ui.same_line();
+                            if ui.button(format!("Load##{slot}")) {
+                                tx.send(EmuThreadCommand::LoadState(slot)).ok();
+                            }
+                        }
+                    });
+                }
+            });
+
         let draw_data = imgui.render();
         unsafe { renderer.gl_context().clear(glow::COLOR_BUFFER_BIT) };
         renderer.render(draw_data).unwrap();
@@ -488,3 +783,96 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// Allocates an RGBA texture with nearest neighbor filtering
+fn create_texture(
+    renderer: &imgui_glow_renderer::AutoRenderer,
+    width: i32,
+    height: i32,
+) -> Result<glow::Texture, String> {
+    unsafe {
+        let ctx = renderer.gl_context();
+        let tex = ctx.create_texture()?;
+        ctx.bind_texture(glow::TEXTURE_2D, Some(tex));
+        ctx.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::NEAREST as _,
+        );
+        ctx.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            glow::NEAREST as _,
+        );
+        ctx.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA as i32,
+            width,
+            height,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            None,
+        );
+        Ok(tex)
+    }
+}
+
+fn upload_texture(
+    renderer: &imgui_glow_renderer::AutoRenderer,
+    tex: glow::Texture,
+    width: i32,
+    height: i32,
+    data: &[u8],
+) {
+    unsafe {
+        renderer
+            .gl_context()
+            .bind_texture(glow::TEXTURE_2D, Some(tex));
+        renderer.gl_context().tex_sub_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            0,
+            0,
+            width,
+            height,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            glow::PixelUnpackData::Slice(data),
+        );
+    }
+}
+
+// Shows a preset picker and color pickers for a palette, returning whether it was changed
+fn edit_palette(ui: &imgui::Ui, label: &str, palette: &mut Palette) -> bool {
+    let _id = ui.push_id(label);
+    let mut changed = false;
+    ui.separator();
+    ui.text(label);
+
+    // Anything that isn't a preset shows up as custom
+    let mut names: Vec<&str> = Palette::PRESETS.iter().map(|(name, _)| *name).collect();
+    names.push("Custom");
+    let mut preset = Palette::PRESETS
+        .iter()
+        .position(|(_, x)| x == palette)
+        .unwrap_or(names.len() - 1);
+    if ui.combo_simple_string("Preset", &mut preset, &names) {
+        if let Some((_, x)) = Palette::PRESETS.get(preset) {
+            *palette = *x;
+            changed = true;
+        }
+    }
+
+    for (i, color) in palette.colors.iter_mut().enumerate() {
+        let mut rgb = [color[0], color[1], color[2]].map(|x| x as f32 / 255.0);
+        if ui.color_edit3(format!("Shade {i}"), &mut rgb) {
+            for (out, x) in color.iter_mut().zip(rgb) {
+                *out = (x * 255.0).round() as u8;
+            }
+            changed = true;
+        }
+    }
+    changed
+}
@@ -0,0 +1,80 @@
+use khangboy_core::Gameboy;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Save state slots, stored next to the ROM as "rom.ss1" to "rom.ss10"
+// Each file is a header with a thumbnail, followed by the core's save state:
+//   "KBSL" magic, u64 UNIX timestamp, 160x144 framebuffer shades
+pub const SLOT_COUNT: usize = 10;
+
+const SLOT_MAGIC: [u8; 4] = *b"KBSL";
+const THUMBNAIL_SIZE: usize = 160 * 144;
+const HEADER_SIZE: usize = SLOT_MAGIC.len() + 8 + THUMBNAIL_SIZE;
+
+pub struct SlotInfo {
+    pub timestamp: u64,
+    pub thumbnail: Box<[u8; THUMBNAIL_SIZE]>,
+}
+
+// Slots are numbered from 1 like the hotkeys
+pub fn slot_path(rom_path: &str, slot: usize) -> PathBuf {
+    Path::new(rom_path).with_extension(format!("ss{slot}"))
+}
+
+pub fn save_slot(gb: &Gameboy, rom_path: &str, slot: usize) -> std::io::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let mut data = Vec::new();
+    data.extend_from_slice(&SLOT_MAGIC);
+    data.extend_from_slice(&timestamp.to_le_bytes());
+    data.extend_from_slice(&gb.components.ppu.framebuffer);
+    data.extend_from_slice(&gb.save_state());
+    std::fs::write(slot_path(rom_path, slot), data)
+}
+
+pub fn load_slot(gb: &mut Gameboy, rom_path: &str, slot: usize) -> Result<(), String> {
+    let data = std::fs::read(slot_path(rom_path, slot)).map_err(|x| x.to_string())?;
+    if data.len() < HEADER_SIZE || data[..SLOT_MAGIC.len()] != SLOT_MAGIC {
+        return Err("Not a save state slot".into());
+    }
+    gb.load_state(&data[HEADER_SIZE..])
+}
+
+// Reads just the header, returning None if the slot is empty or invalid
+pub fn read_slot_info(rom_path: &str, slot: usize) -> Option<SlotInfo> {
+    let data = std::fs::read(slot_path(rom_path, slot)).ok()?;
+    if data.len() < HEADER_SIZE || data[..SLOT_MAGIC.len()] != SLOT_MAGIC {
+        return None;
+    }
+    let timestamp = u64::from_le_bytes(data[4..12].try_into().unwrap());
+    let thumbnail = Box::new(data[12..HEADER_SIZE].try_into().unwrap());
+    Some(SlotInfo {
+        timestamp,
+        thumbnail,
+    })
+}
+
+// Formats a UNIX timestamp as a UTC date and time
+// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}